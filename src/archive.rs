@@ -0,0 +1,321 @@
+use std::fs::File;
+use std::io::{self, Read, Seek};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::decompress::{Compression, MAGIC_LEN};
+use crate::error::SfindError;
+
+// The archives that --archives searches inside as if they were folders
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ArchiveKind {
+    Zip,
+    Tar,
+}
+
+// A file inside an archive
+#[derive(Debug)]
+pub struct ArchiveMember {
+    // the path of the archive then !/ and the path of the file in the archive
+    pub path: PathBuf,
+    // only read when the contents are to be searched
+    pub contents: Option<Vec<u8>>,
+}
+
+// the most of a file in an archive that is read when --max-size is not given
+pub const MEMBER_SIZE_LIMIT: u64 = 1 << 30;
+
+const ZIP_EXTENSIONS: &[&str] = &[".zip", ".jar", ".war", ".ear"];
+const TAR_EXTENSIONS: &[&str] = &[".tar", ".tgz", ".tbz2", ".txz", ".tar.gz", ".tar.bz2", ".tar.xz", ".tar.zst"];
+
+impl ArchiveKind {
+    // the kind of archive from the extension of its file name
+    pub fn from_path(path: &Path) -> Option<ArchiveKind> {
+        let name = path.file_name()?.to_string_lossy().to_lowercase();
+        if ZIP_EXTENSIONS.iter().any(|extension| name.ends_with(extension)) {
+            Some(ArchiveKind::Zip)
+        } else if TAR_EXTENSIONS.iter().any(|extension| name.ends_with(extension)) {
+            Some(ArchiveKind::Tar)
+        } else {
+            None
+        }
+    }
+}
+
+// the path of a file inside an archive, lib/foo.jar!/com/x/Y.class
+pub fn member_path(archive: &Path, name: &str) -> PathBuf {
+    // tar files made from . have names like ./com/x/Y.class
+    let name: Vec<&str> = name.split('/').filter(|part| !part.is_empty() && *part != ".").collect();
+    PathBuf::from(format!("{}!/{}", archive.display(), name.join("/")))
+}
+
+// days since 1970-01-01 of a date in the proleptic Gregorian calendar
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+// zip times have no time zone, take them to be UTC
+fn zip_time(time: zip::DateTime) -> Option<SystemTime> {
+    let days = days_from_civil(time.year() as i64, time.month() as i64, time.day() as i64);
+    let secs = days * 86400 + time.hour() as i64 * 3600 + time.minute() as i64 * 60 + time.second() as i64;
+    Some(UNIX_EPOCH + Duration::from_secs(u64::try_from(secs).ok()?))
+}
+
+// Reads the selected files of an archive one at a time, so that only one
+// file's contents are in memory however big the archive is. The archive is
+// read on a thread of its own because a tar reader borrows the archive.
+pub struct ArchiveReader {
+    messages: mpsc::Receiver<ReaderMessage>,
+    selected: mpsc::SyncSender<bool>,
+}
+
+enum ReaderMessage {
+    // the path, size and modification time of the next file in the archive
+    Entry(PathBuf, u64, Option<SystemTime>),
+    // the file after it was selected
+    Member(Result<ArchiveMember, SfindError>),
+}
+
+// the archive thread's end of the channels
+struct MemberSender {
+    messages: mpsc::SyncSender<ReaderMessage>,
+    selected: mpsc::Receiver<bool>,
+}
+
+impl ArchiveReader {
+    // contents_limit is the most bytes of a file's contents to read, None to
+    // read only the names
+    pub fn open(path: PathBuf, kind: ArchiveKind, contents_limit: Option<u64>) -> ArchiveReader {
+        // with no buffer each send waits for the other side
+        let (message_sender, messages) = mpsc::sync_channel(0);
+        let (selected_sender, selected) = mpsc::sync_channel(0);
+        thread::spawn(move || {
+            let sender = MemberSender { messages: message_sender, selected };
+            if let Err(e) = read_archive(&path, kind, contents_limit, &sender) {
+                sender.send(Err(SfindError::io(&path, e)));
+            }
+        });
+        ArchiveReader { messages, selected: selected_sender }
+    }
+
+    // The next file in the archive that selected returns true for. selected is
+    // given the path of the member, its size and its modification time. A
+    // member that cannot be read is returned as an error and the rest are
+    // still read.
+    pub fn next_member<F>(&mut self, mut selected: F) -> Option<Result<ArchiveMember, SfindError>>
+    where
+        F: FnMut(&Path, u64, Option<SystemTime>) -> bool,
+    {
+        loop {
+            match self.messages.recv().ok()? {
+                ReaderMessage::Entry(member, size, modified) => {
+                    self.selected.send(selected(&member, size, modified)).ok()?;
+                }
+                ReaderMessage::Member(member) => return Some(member),
+            }
+        }
+    }
+}
+
+impl MemberSender {
+    // an error when the ArchiveReader has been dropped stops the reading
+    fn selected(&self, member: &Path, size: u64, modified: Option<SystemTime>) -> io::Result<bool> {
+        let closed = || io::Error::from(io::ErrorKind::BrokenPipe);
+        self.messages.send(ReaderMessage::Entry(member.to_path_buf(), size, modified)).map_err(|_| closed())?;
+        self.selected.recv().map_err(|_| closed())
+    }
+
+    fn send(&self, member: Result<ArchiveMember, SfindError>) {
+        let _ = self.messages.send(ReaderMessage::Member(member));
+    }
+}
+
+// an error returned here stops the rest of the archive being read
+fn read_archive(path: &Path, kind: ArchiveKind, contents_limit: Option<u64>, sender: &MemberSender) -> io::Result<()> {
+    let mut file = File::open(path)?;
+    match kind {
+        ArchiveKind::Zip => {
+            let invalid_data = |e: zip::result::ZipError| io::Error::new(io::ErrorKind::InvalidData, e);
+            let mut archive = zip::ZipArchive::new(file).map_err(invalid_data)?;
+            for index in 0..archive.len() {
+                let member = member_path(path, archive.name_for_index(index).unwrap_or_default());
+                // the raw entry has the name, size and time even when its
+                // compression method is not one that can be read
+                let entry = match archive.by_index_raw(index) {
+                    Ok(entry) => entry,
+                    Err(e) => {
+                        sender.send(Err(SfindError::io(&member, invalid_data(e))));
+                        continue;
+                    }
+                };
+                if entry.is_dir() {
+                    continue;
+                }
+                let modified = entry.last_modified().and_then(zip_time);
+                let size = entry.size();
+                drop(entry);
+                if !sender.selected(&member, size, modified)? {
+                    continue;
+                }
+                let contents = match contents_limit {
+                    Some(limit) => archive.by_index(index).map_err(invalid_data).and_then(|mut entry| read_contents(&mut entry, limit)),
+                    None => Ok(None),
+                };
+                sender.send(member_read(member, contents));
+            }
+        }
+        ArchiveKind::Tar => {
+            // a compressed tar is decompressed as it is read
+            let mut magic = vec![];
+            (&mut file).take(MAGIC_LEN as u64).read_to_end(&mut magic)?;
+            file.rewind()?;
+            let reader: Box<dyn Read> = match Compression::from_magic(&magic) {
+                Some(compression) => compression.decoder(file)?,
+                None => Box::new(file),
+            };
+            let mut archive = tar::Archive::new(reader);
+            for entry in archive.entries()? {
+                let mut entry = entry?;
+                // links and folders have no contents of their own
+                if !entry.header().entry_type().is_file() {
+                    continue;
+                }
+                let member = member_path(path, &entry.path()?.to_string_lossy());
+                let modified = entry.header().mtime().ok().map(|secs| UNIX_EPOCH + Duration::from_secs(secs));
+                let size = entry.size();
+                if !sender.selected(&member, size, modified)? {
+                    continue;
+                }
+                let contents = match contents_limit {
+                    Some(limit) => read_contents(&mut entry, limit),
+                    None => Ok(None),
+                };
+                sender.send(member_read(member, contents));
+            }
+        }
+    }
+    Ok(())
+}
+
+// the size in the header comes from the archive and cannot be trusted, one
+// byte more than the limit is read to find a file that is too big
+fn read_contents(reader: &mut dyn Read, limit: u64) -> io::Result<Option<Vec<u8>>> {
+    let mut contents = vec![];
+    reader.take(limit.saturating_add(1)).read_to_end(&mut contents)?;
+    if contents.len() as u64 > limit {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("more than {limit} bytes to read")));
+    }
+    Ok(Some(contents))
+}
+
+fn member_read(member: PathBuf, contents: io::Result<Option<Vec<u8>>>) -> Result<ArchiveMember, SfindError> {
+    match contents {
+        Ok(contents) => Ok(ArchiveMember { path: member, contents }),
+        Err(e) => Err(SfindError::io(&member, e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::ScratchFolder;
+
+    #[test]
+    fn archive_kinds() {
+        assert_eq!(ArchiveKind::from_path(Path::new("lib/foo.jar")), Some(ArchiveKind::Zip));
+        assert_eq!(ArchiveKind::from_path(Path::new("Backup.ZIP")), Some(ArchiveKind::Zip));
+        assert_eq!(ArchiveKind::from_path(Path::new("src-1.0.tar.gz")), Some(ArchiveKind::Tar));
+        assert_eq!(ArchiveKind::from_path(Path::new("app.log.gz")), None);
+        assert_eq!(ArchiveKind::from_path(Path::new("jar")), None);
+        assert_eq!(member_path(Path::new("./lib/foo.jar"), "com/x/Y.class"), PathBuf::from("./lib/foo.jar!/com/x/Y.class"));
+        assert_eq!(member_path(Path::new("src.tgz"), "./com//Y.java"), PathBuf::from("src.tgz!/com/Y.java"));
+    }
+
+    #[test]
+    fn civil_dates() {
+        assert_eq!(days_from_civil(1970, 1, 1), 0);
+        assert_eq!(days_from_civil(2000, 3, 1), 11017);
+        assert_eq!(days_from_civil(2024, 2, 29), 19782);
+    }
+
+    // a zip of first.txt stored and second.txt marked as LZMA, which cannot be read
+    fn mixed_zip(path: &Path) {
+        let mut zip = zip::ZipWriter::new(io::Cursor::new(vec![]));
+        let options = zip::write::SimpleFileOptions::default().compression_method(zip::CompressionMethod::Stored);
+        for name in ["first.txt", "second.txt"] {
+            zip.start_file(name, options).unwrap();
+            io::Write::write_all(&mut zip, b"key=1\n").unwrap();
+        }
+        let mut bytes = zip.finish().unwrap().into_inner();
+        // the compression method is at 8 in the local header and 10 in the central directory
+        for (magic, offset) in [(b"PK\x03\x04", 8), (b"PK\x01\x02", 10)] {
+            let second = bytes.windows(4).enumerate().filter(|(_, window)| window == magic).nth(1).unwrap().0;
+            bytes[second + offset] = 14;
+        }
+        std::fs::write(path, bytes).unwrap();
+    }
+
+    #[test]
+    fn unreadable_member() {
+        let folder = ScratchFolder::new("mixed");
+        let path = folder.join("mixed.zip");
+        mixed_zip(&path);
+
+        let mut names = ArchiveReader::open(path.clone(), ArchiveKind::Zip, None);
+        assert_eq!(names.next_member(|_, _, _| true).unwrap().unwrap().path, member_path(&path, "first.txt"));
+        assert_eq!(names.next_member(|_, _, _| true).unwrap().unwrap().path, member_path(&path, "second.txt"));
+        assert!(names.next_member(|_, _, _| true).is_none());
+
+        let mut members = ArchiveReader::open(path.clone(), ArchiveKind::Zip, Some(MEMBER_SIZE_LIMIT));
+        let first = members.next_member(|_, _, _| true).unwrap().unwrap();
+        assert_eq!(first.path, member_path(&path, "first.txt"));
+        assert_eq!(first.contents.as_deref(), Some(&b"key=1\n"[..]));
+        assert!(matches!(members.next_member(|_, _, _| true), Some(Err(SfindError::Io { .. }))));
+        assert!(members.next_member(|_, _, _| true).is_none());
+
+        // the members that are not selected are passed over
+        let mut second = ArchiveReader::open(path.clone(), ArchiveKind::Zip, None);
+        let selected = |member: &Path, _, _| member.ends_with("second.txt");
+        assert_eq!(second.next_member(selected).unwrap().unwrap().path, member_path(&path, "second.txt"));
+        assert!(second.next_member(selected).is_none());
+    }
+
+    #[test]
+    fn oversized_member() {
+        let folder = ScratchFolder::new("oversized");
+        let mut tar = tar::Builder::new(vec![]);
+        for (name, contents) in [("first.txt", "key=1\n"), ("second.txt", "k\n")] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(contents.len() as u64);
+            tar.append_data(&mut header, name, contents.as_bytes()).unwrap();
+        }
+        let path = folder.write("limit.tar", tar.into_inner().unwrap());
+
+        // a file bigger than the limit is reported and the rest are still read
+        let mut members = ArchiveReader::open(path.clone(), ArchiveKind::Tar, Some(4));
+        assert!(matches!(members.next_member(|_, _, _| true), Some(Err(SfindError::Io { .. }))));
+        let second = members.next_member(|_, _, _| true).unwrap().unwrap();
+        assert_eq!(second.contents.as_deref(), Some(&b"k\n"[..]));
+        assert!(members.next_member(|_, _, _| true).is_none());
+
+        // a size in the header that is far too big is not believed
+        let mut header = tar::Header::new_gnu();
+        header.set_path("big.txt").unwrap();
+        header.set_size(1 << 50);
+        header.set_cksum();
+        let mut bytes = header.as_bytes().to_vec();
+        bytes.extend_from_slice(b"key=1\n");
+        let path = folder.write("big.tar", bytes);
+        let mut members = ArchiveReader::open(path, ArchiveKind::Tar, Some(MEMBER_SIZE_LIMIT));
+        let big = members.next_member(|_, _, _| true).unwrap().unwrap();
+        assert_eq!(big.contents.as_deref(), Some(&b"key=1\n"[..]));
+        assert!(matches!(members.next_member(|_, _, _| true), Some(Err(SfindError::Io { .. }))));
+    }
+}
@@ -0,0 +1,175 @@
+use serde;
+
+// The colours as written in the config file.
+//
+// Each colour is a space separated list of words:
+//  * a colour name: black, red, green, yellow, blue, magenta (or purple), cyan, white
+//    optionally prefixed with bright-
+//  * a 256 colour number: 0 to 255
+//  * a truecolor value: #rrggbb
+//  * any of the above prefixed with on- to set the background colour
+//  * an attribute: bold, dim, italic, underline, reverse
+// An empty string means do not colour.
+#[derive(serde::Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct ColourTheme {
+    pub file: String,
+    pub line_number: String,
+    pub separator: String,
+    pub context: String,
+    pub matches: Vec<String>,
+}
+
+impl Default for ColourTheme {
+    fn default() -> ColourTheme {
+        ColourTheme {
+            file: "magenta".to_string(),
+            line_number: "green".to_string(),
+            separator: "".to_string(),
+            context: "".to_string(),
+            matches: ["bold red", "yellow", "bold blue", "green", "magenta"]
+                .iter()
+                .map(|spec| spec.to_string())
+                .collect(),
+        }
+    }
+}
+
+// The escape sequences to write for each part of the output
+#[derive(Debug, Clone, Default)]
+pub struct Colours {
+    pub file: String,
+    pub line_number: String,
+    pub separator: String,
+    pub context: String,
+    pub matches: Vec<String>,
+    pub end: &'static str,
+}
+
+impl Colours {
+    pub fn new(theme: &ColourTheme) -> Result<Colours, String> {
+        Ok(Colours {
+            file: sgr_sequence(&theme.file)?,
+            line_number: sgr_sequence(&theme.line_number)?,
+            separator: sgr_sequence(&theme.separator)?,
+            context: sgr_sequence(&theme.context)?,
+            matches: theme
+                .matches
+                .iter()
+                .map(|spec| sgr_sequence(spec))
+                .collect::<Result<Vec<_>, _>>()?,
+            end: "\x1b[m",
+        })
+    }
+
+    // for output that must not be coloured
+    pub fn none() -> Colours {
+        Colours::default()
+    }
+
+    // wrap text in the colour, if there is one
+    pub fn paint(&self, colour: &str, text: &str) -> String {
+        if colour.is_empty() {
+            text.to_string()
+        } else {
+            format!("{}{}{}", colour, text, self.end)
+        }
+    }
+}
+
+fn colour_code(name: &str, background: bool) -> Result<String, String> {
+    let base = if background { 40 } else { 30 };
+    let extended = if background { 48 } else { 38 };
+
+    if let Some(hex) = name.strip_prefix('#') {
+        if hex.len() != 6 || !hex.chars().all(|ch| ch.is_ascii_hexdigit()) {
+            return Err(format!("expecting #rrggbb not {:?}", name));
+        }
+        let component = |index: usize| u8::from_str_radix(&hex[index..index + 2], 16).unwrap();
+        return Ok(format!("{};2;{};{};{}", extended, component(0), component(2), component(4)));
+    }
+
+    if let Ok(number) = name.parse::<u8>() {
+        return Ok(format!("{};5;{}", extended, number));
+    }
+
+    let (name, bright) = match name.strip_prefix("bright-") {
+        Some(name) => (name, true),
+        None => (name, false),
+    };
+    let offset = match name {
+        "black" => 0,
+        "red" => 1,
+        "green" => 2,
+        "yellow" => 3,
+        "blue" => 4,
+        "magenta" | "purple" => 5,
+        "cyan" => 6,
+        "white" => 7,
+        _ => {
+            return Err(format!("unknown colour {:?}", name));
+        }
+    };
+    Ok((if bright { base + 60 } else { base } + offset).to_string())
+}
+
+// convert a colour spec into the escape sequence that selects it
+pub fn sgr_sequence(spec: &str) -> Result<String, String> {
+    let mut codes = vec![];
+    for word in spec.split_whitespace() {
+        let code = match word {
+            "bold" => "1".to_string(),
+            "dim" => "2".to_string(),
+            "italic" => "3".to_string(),
+            "underline" => "4".to_string(),
+            "reverse" => "7".to_string(),
+            _ => match word.strip_prefix("on-") {
+                Some(background) => colour_code(background, true)?,
+                None => colour_code(word, false)?,
+            },
+        };
+        codes.push(code);
+    }
+
+    if codes.is_empty() {
+        Ok(String::new())
+    } else {
+        Ok(format!("\x1b[{}m", codes.join(";")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn named_colours() {
+        assert_eq!(sgr_sequence("").unwrap(), "");
+        assert_eq!(sgr_sequence("magenta").unwrap(), "\x1b[35m");
+        assert_eq!(sgr_sequence("purple").unwrap(), "\x1b[35m");
+        assert_eq!(sgr_sequence("bold red").unwrap(), "\x1b[1;31m");
+        assert_eq!(sgr_sequence("bright-yellow").unwrap(), "\x1b[93m");
+        assert_eq!(sgr_sequence("black on-white").unwrap(), "\x1b[30;47m");
+        assert!(sgr_sequence("mauve").is_err());
+    }
+
+    #[test]
+    fn extended_colours() {
+        assert_eq!(sgr_sequence("208").unwrap(), "\x1b[38;5;208m");
+        assert_eq!(sgr_sequence("on-17").unwrap(), "\x1b[48;5;17m");
+        assert_eq!(sgr_sequence("#ff8800").unwrap(), "\x1b[38;2;255;136;0m");
+        assert_eq!(sgr_sequence("underline on-#000080").unwrap(), "\x1b[4;48;2;0;0;128m");
+        assert!(sgr_sequence("256").is_err());
+        assert!(sgr_sequence("#ff88").is_err());
+    }
+
+    #[test]
+    fn default_theme() {
+        let colours = Colours::new(&ColourTheme::default()).unwrap();
+        assert_eq!(colours.file, "\x1b[35m");
+        assert_eq!(colours.line_number, "\x1b[32m");
+        assert_eq!(colours.matches, vec!["\x1b[1;31m", "\x1b[33m", "\x1b[1;34m", "\x1b[32m", "\x1b[35m"]);
+        assert_eq!(colours.paint(&colours.file, "x"), "\x1b[35mx\x1b[m");
+        assert_eq!(colours.paint(&colours.separator, ":"), ":");
+    }
+}
@@ -1,9 +1,60 @@
-use anyhow::{anyhow, Result};
-use clap::Parser;
-use std::path::PathBuf;
+use clap::{Parser, ValueEnum};
+use std::collections::HashMap;
+use std::env;
+use std::io::{self, IsTerminal};
+use std::fs;
+use std::path::{Path, PathBuf};
 use indoc::indoc;
+use encoding_rs::Encoding;
 use std::time::{SystemTime, Duration};
 
+use crate::config_json::ConfigJson;
+use crate::error::SfindError;
+use crate::find_files::FindFiles;
+
+type Result<T> = std::result::Result<T, SfindError>;
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum ColourMode {
+    Auto,
+    Always,
+    Never,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum OutputFormat {
+    // path:line: text padded to line up the text
+    Default,
+    // path:line:col: text of the line with the first match
+    Emacs,
+    // path:line:col:text for every match
+    Vimgrep,
+    // JSON Lines
+    Json,
+}
+
+// what to do with a file that looks binary
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum BinaryMode {
+    // do not search it
+    Skip,
+    // print one line when a pattern matches anywhere in it
+    Report,
+    // search it as text like any other file
+    Text,
+    // show each match in a hex dump of the bytes around it
+    Hex,
+}
+
+// what to print for each file that is grep'ed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GrepReport {
+    Lines,
+    FilesWithMatches,
+    FilesWithoutMatch,
+    Count,
+}
+
 #[derive(Debug, Parser)]
 #[command(name = "sfind")]
 #[command(version = env!("CARGO_PKG_VERSION"))]
@@ -28,7 +79,25 @@ struct Cli {
     #[arg(short = 's', long = "sensitive", help = "match regex case sensitively")]
     pub case_sensitive_contents: bool,
 
-    #[arg(short = 'p', long = "path", help = "match file names anywhere in the full path")]
+    #[arg(long = "smart-case", help = "match case sensitively when the pattern has upper case letters")]
+    pub smart_case: bool,
+
+    #[arg(long = "no-smart-case", conflicts_with = "smart_case", help = "turn off smart_case set in the config")]
+    pub no_smart_case: bool,
+
+    #[arg(short = 'w', long = "word-regexp", help = "only match whole words")]
+    pub word_regexp: bool,
+
+    #[arg(long = "no-word-regexp", conflicts_with = "word_regexp", help = "turn off word_regexp set in the config")]
+    pub no_word_regexp: bool,
+
+    #[arg(short = 'x', long = "line-regexp", help = "only match whole lines")]
+    pub line_regexp: bool,
+
+    #[arg(long = "no-line-regexp", conflicts_with = "line_regexp", help = "turn off line_regexp set in the config")]
+    pub no_line_regexp: bool,
+
+    #[arg(short = 'p', long = "path", help = "match the file name patterns against the path inside the folder searched or the full path, **/ matches any folders and, as in earlier versions, * and ? also match /")]
     pub match_path: bool,
 
     #[arg(short = 't', long = "times", help = indoc! {"
@@ -57,18 +126,144 @@ struct Cli {
     #[arg(short, long, value_name = "LINES", help = "lines to show before match")]
     pub before: Option<usize>,
 
+    #[arg(short = 'C', long, value_name = "LINES", help = "lines to show before and after match")]
+    pub context: Option<usize>,
+
+    #[arg(long = "group-separator", value_name = "SEP", help = "print SEP between groups of lines that are not next to each other, defaults to --")]
+    pub group_separator: Option<String>,
+
+    #[arg(long = "no-group-separator", conflicts_with = "group_separator", help = "do not print a separator between groups of lines")]
+    pub no_group_separator: bool,
+
+    #[arg(long, help = "print the path once above the lines found in each file")]
+    pub heading: bool,
+
+    #[arg(long, help = "print the column of the first match in each line, counted in characters from 1")]
+    pub column: bool,
+
+    #[arg(long = "byte-offset", help = "print the offset in the file of the first match in each line, counted in bytes from 0")]
+    pub byte_offset: bool,
+
+    #[arg(long = "no-ignore", help = "do not use .gitignore, .ignore and .sfindignore files")]
+    pub no_ignore: bool,
+
+    #[arg(long, help = "search inside zip, jar and tar archives as if they were folders, files in them are shown as archive!/path")]
+    pub archives: bool,
+
     #[arg(short, long, help = "number of folder levels to search")]
     pub depth: Option<usize>,
 
+    #[arg(short = 'j', long, value_name = "N", help = "number of threads to search with, defaults to the number of CPUs")]
+    pub threads: Option<usize>,
+
+    #[arg(long, help = "print results in the same order as a single threaded search")]
+    pub ordered: bool,
+
     #[arg(long, help = "report supressed errors")]
     pub errors: bool,
 
+    #[arg(long = "color", visible_alias = "colour", value_name = "WHEN", default_value = "auto", help = indoc! {"
+        colour the output: auto, always or never
+        auto colours when the output is a terminal and NO_COLOR is not set"
+        })]
+    pub colour: ColourMode,
+
+    #[arg(long, value_name = "FORMAT", default_value = "default", help = indoc! {"
+        output format: default, emacs, vimgrep or json
+        emacs and vimgrep include the column of the match for editors to jump to"
+        })]
+    pub format: OutputFormat,
+
+    #[arg(long, conflicts_with = "format", help = "print the results as JSON, one object per line, same as --format json")]
+    pub json: bool,
+
+    #[arg(short = 'l', long, conflicts_with_all = ["files_without_match", "count"], help = "only print the names of files that match")]
+    pub files_with_matches: bool,
+
+    #[arg(short = 'L', long, conflicts_with = "count", help = "only print the names of files that do not match")]
+    pub files_without_match: bool,
+
+    #[arg(short, long, help = "only print the number of matching lines in each file")]
+    pub count: bool,
+
     #[arg(short, long, value_name = "REGEX", help = "regex pattern to find")]
     pub regex: Vec<String>,
 
     #[arg(short, long, value_name = "STR", help = "fixed string to find")]
     pub fixed: Vec<String>,
 
+    #[arg(long = "fixed-file", value_name = "PATH", help = "read fixed strings to find from PATH, one per line")]
+    pub fixed_file: Vec<PathBuf>,
+
+    #[arg(long = "regex-file", value_name = "PATH", help = "read regex patterns to find from PATH, one per line")]
+    pub regex_file: Vec<PathBuf>,
+
+    #[arg(long = "show-patterns", help = "print the patterns that matched before each line, the default with --fixed-file and --regex-file")]
+    pub show_patterns: bool,
+
+    #[arg(long = "no-show-patterns", conflicts_with = "show_patterns", help = "do not print the patterns that matched")]
+    pub no_show_patterns: bool,
+
+    #[arg(long = "not-regex", value_name = "REGEX", help = "do not match lines that match this regex")]
+    pub not_regex: Vec<String>,
+
+    #[arg(long = "not-fixed", value_name = "STR", help = "do not match lines that contain this string")]
+    pub not_fixed: Vec<String>,
+
+    #[arg(short = 'v', long = "invert-match", help = "print the lines that do not match")]
+    pub invert_match: bool,
+
+    #[arg(long = "all-of", conflicts_with = "invert_match", help = "only report files in which every pattern matches")]
+    pub all_of: bool,
+
+    #[arg(long = "none-of", value_name = "REGEX", help = "do not report files in which this regex matches")]
+    pub none_of: Vec<String>,
+
+    #[arg(short = 'U', long, conflicts_with = "invert_match", help = "let the regex patterns match across lines")]
+    pub multiline: bool,
+
+    #[arg(short = 'o', long = "only-matching", conflicts_with_all = ["invert_match", "replace"], help = "print each match on its own line instead of the whole line")]
+    pub only_matching: bool,
+
+    #[arg(long = "only-group", value_name = "N", requires = "only_matching", help = "with -o print capture group N of each match")]
+    pub only_group: Option<usize>,
+
+    #[arg(long, value_name = "TEMPLATE", conflicts_with = "invert_match", help = indoc! {"
+        print the lines with each match replaced by TEMPLATE
+        $1 or ${name} in TEMPLATE is replaced by that capture group of the regex"
+        })]
+    pub replace: Option<String>,
+
+    #[arg(long, requires = "replace", conflicts_with_all = ["files_with_matches", "files_without_match", "count", "json", "format"],
+        help = "print the replacements as a unified diff for git apply or patch")]
+    pub diff: bool,
+
+    #[arg(long, requires = "replace", conflicts_with_all = ["diff", "files_with_matches", "files_without_match", "count", "json", "format"],
+        help = "write the replacements back to the files")]
+    pub write: bool,
+
+    #[arg(long, requires = "write", help = "keep a copy of each file changed by --write as file.orig, an existing file.orig is never replaced and .orig files are not changed")]
+    pub backup: bool,
+
+    #[arg(long = "dry-run", requires = "write", help = "list the files --write would change with their number of replacements")]
+    pub dry_run: bool,
+
+    #[arg(short = 'z', long, help = "search inside gzip, xz, bzip2 and zstd compressed files")]
+    pub decompress: bool,
+
+    #[arg(long, value_name = "MODE", default_value = "skip", help = indoc! {"
+        binary files: skip, report, text or hex
+        report prints which binary files match and hex shows the matches in a hex dump"
+        })]
+    pub binary: BinaryMode,
+
+    #[arg(long, value_name = "NAME", help = indoc! {"
+        read the files in encoding NAME, for example utf-16le, latin1 or shift_jis
+        auto, the default, uses the byte order mark then the config then UTF-8
+        falling back to Windows-1252"
+        })]
+    pub encoding: Option<String>,
+
     #[arg(value_name = "PATH", help = "Files and Folders to find")]
     pub positional: Vec<PathBuf>,
 
@@ -90,13 +285,52 @@ pub struct CommandOptions {
     pub size_min: Option<u64>,
     pub size_max: Option<u64>,
     pub grep_ignore_case: bool,
+    pub grep_smart_case: bool,
+    pub grep_word_regexp: bool,
+    pub grep_line_regexp: bool,
+    pub grep_invert_match: bool,
+    pub grep_all_of: bool,
+    pub grep_multiline: bool,
+    pub grep_only_matching: bool,
+    pub grep_only_group: Option<usize>,
+    pub grep_replacement: Option<String>,
+    pub replace_diff: bool,
+    pub replace_write: bool,
+    pub replace_backup: bool,
+    pub replace_dry_run: bool,
     pub grep_lines_after: Option<usize>,
     pub grep_lines_before: Option<usize>,
+    // None until the config default is applied, empty for no separator
+    pub group_separator: Option<String>,
+    pub output_heading: bool,
+    pub show_column: bool,
+    pub show_byte_offset: bool,
+    pub show_patterns: bool,
+    pub decompress: bool,
+    pub binary_mode: BinaryMode,
+    // None to work out the encoding of each file
+    pub encoding: Option<&'static Encoding>,
+    // the encodings the config gives to file extensions
+    pub extension_encodings: HashMap<String, &'static Encoding>,
+    pub output_format: OutputFormat,
+    pub grep_report: GrepReport,
+    pub colour_output: bool,
     pub find_depth: Option<usize>,
+    pub find_use_ignore_files: bool,
+    pub find_archives: bool,
+    pub threads: usize,
+    pub ordered_output: bool,
     pub regex_patterns: Vec<String>,
     pub fixed_strings: Vec<String>,
+    pub not_regex_patterns: Vec<String>,
+    pub not_fixed_strings: Vec<String>,
+    pub none_of_patterns: Vec<String>,
     pub folders: Vec<PathBuf>,
     pub files: Vec<String>,
+    // the match options given on the command line, the config has the defaults
+    smart_case_flag: Option<bool>,
+    word_regexp_flag: Option<bool>,
+    line_regexp_flag: Option<bool>,
 }
 
 impl CommandOptions {
@@ -106,17 +340,31 @@ impl CommandOptions {
         let progname = match iargs.next() {
             Some(arg) => arg.clone(),
             None => {
-                return Err(anyhow!("missing progname in command arguments"));
+                return Err(SfindError::MissingProgname);
             }
         };
 
-        let cli = Cli::try_parse()?;
+        let cli = Cli::try_parse_from(args)?;
 
         // parse times
         let (time_from, time_till) = Self::parse_times(&cli.times)?;
         let min_size = Self::parse_size(&cli.min_size)?;
         let max_size = Self::parse_size(&cli.max_size)?;
 
+        let mut fixed_strings = cli.fixed;
+        for path in &cli.fixed_file {
+            fixed_strings.extend(Self::read_patterns(path)?);
+        }
+        let mut regex_patterns = cli.regex;
+        for path in &cli.regex_file {
+            regex_patterns.extend(Self::read_patterns(path)?);
+        }
+        // pattern files without any patterns are an error rather than every file being listed
+        let pattern_files: Vec<PathBuf> = cli.fixed_file.iter().chain(&cli.regex_file).cloned().collect();
+        if !pattern_files.is_empty() && fixed_strings.is_empty() && regex_patterns.is_empty() {
+            return Err(SfindError::NoPatterns(pattern_files));
+        }
+
         let mut opt = CommandOptions {
             progname,
             debug: cli.debug,
@@ -125,18 +373,62 @@ impl CommandOptions {
             report_supressed_errors: cli.errors,
             find_iname: !cli.case_sensitive_filenames,
             find_match_basename: !cli.match_path,
-            time_from: time_from,
-            time_till: time_till,
+            time_from,
+            time_till,
             size_min: min_size,
             size_max: max_size,
             grep_ignore_case: !cli.case_sensitive_contents,
-            grep_lines_after: cli.after,
-            grep_lines_before: cli.before,
+            grep_smart_case: cli.smart_case,
+            grep_word_regexp: cli.word_regexp,
+            grep_line_regexp: cli.line_regexp,
+            grep_invert_match: cli.invert_match,
+            grep_all_of: cli.all_of,
+            grep_multiline: cli.multiline,
+            grep_only_matching: cli.only_matching,
+            grep_only_group: cli.only_group,
+            grep_replacement: cli.replace,
+            replace_diff: cli.diff,
+            replace_write: cli.write,
+            replace_backup: cli.backup,
+            replace_dry_run: cli.dry_run,
+            grep_lines_after: cli.after.or(cli.context),
+            grep_lines_before: cli.before.or(cli.context),
+            group_separator: if cli.no_group_separator { Some(String::new()) } else { cli.group_separator },
+            output_heading: cli.heading,
+            show_column: cli.column,
+            show_byte_offset: cli.byte_offset,
+            // the patterns of a pattern file are reported unless turned off
+            show_patterns: Self::flag(cli.show_patterns, cli.no_show_patterns).unwrap_or(!pattern_files.is_empty()),
+            decompress: cli.decompress,
+            binary_mode: cli.binary,
+            encoding: Self::parse_encoding(&cli.encoding)?,
+            extension_encodings: HashMap::new(),
+            output_format: if cli.json { OutputFormat::Json } else { cli.format },
+            grep_report: if cli.files_with_matches {
+                GrepReport::FilesWithMatches
+            } else if cli.files_without_match {
+                GrepReport::FilesWithoutMatch
+            } else if cli.count {
+                GrepReport::Count
+            } else {
+                GrepReport::Lines
+            },
+            colour_output: Self::use_colour(cli.colour),
             find_depth: cli.depth,
-            regex_patterns: cli.regex,
-            fixed_strings: cli.fixed,
+            find_use_ignore_files: !cli.no_ignore,
+            find_archives: cli.archives,
+            threads: Self::number_of_threads(cli.threads)?,
+            ordered_output: cli.ordered,
+            regex_patterns,
+            fixed_strings,
+            not_regex_patterns: cli.not_regex,
+            not_fixed_strings: cli.not_fixed,
+            none_of_patterns: cli.none_of,
             folders: vec![],
             files: vec![],
+            smart_case_flag: Self::flag(cli.smart_case, cli.no_smart_case),
+            word_regexp_flag: Self::flag(cli.word_regexp, cli.no_word_regexp),
+            line_regexp_flag: Self::flag(cli.line_regexp, cli.no_line_regexp),
         };
 
         for path in cli.positional {
@@ -148,7 +440,7 @@ impl CommandOptions {
                         opt.files.push(file.to_string());
                     }
                     None => {
-                        eprintln!("filename is not utf-8 {}", path.display());
+                        return Err(SfindError::NonUtf8Path(path));
                     }
                 };
             }
@@ -158,9 +450,102 @@ impl CommandOptions {
             opt.folders.push(PathBuf::from("."));
         }
 
+        // a bad file name pattern is reported before any folder is searched
+        FindFiles::match_filenames_regex(&opt.files, opt.find_iname)?;
+
         Ok(opt)
     }
 
+    // use the config for the options that were not given on the command line
+    pub fn apply_config(&mut self, config: &ConfigJson) {
+        if self.group_separator.is_none() {
+            self.group_separator = Some(config.group_separator.clone());
+        }
+        let defaults = &config.match_defaults;
+        self.grep_smart_case = self.smart_case_flag.unwrap_or(defaults.smart_case);
+        self.grep_word_regexp = self.word_regexp_flag.unwrap_or(defaults.word_regexp);
+        self.grep_line_regexp = self.line_regexp_flag.unwrap_or(defaults.line_regexp);
+        // the labels have been checked when the config was loaded
+        self.extension_encodings = config
+            .encodings
+            .iter()
+            .filter_map(|(extension, label)| {
+                Some((extension.to_lowercase(), Encoding::for_label(label.as_bytes())?))
+            })
+            .collect();
+    }
+
+    // an encoding_rs label, auto or no --encoding to work it out for each file
+    fn parse_encoding(name: &Option<String>) -> std::result::Result<Option<&'static Encoding>, SfindError> {
+        match name.as_deref() {
+            None | Some("auto") => Ok(None),
+            Some(name) => match Encoding::for_label(name.as_bytes()) {
+                Some(encoding) => Ok(Some(encoding)),
+                None => Err(SfindError::UnknownEncoding(name.to_string())),
+            },
+        }
+    }
+
+    fn read_patterns(path: &Path) -> std::result::Result<Vec<String>, SfindError> {
+        let text = fs::read_to_string(path).map_err(|e| SfindError::io(path, e))?;
+        Ok(Self::parse_patterns(&text))
+    }
+
+    // one pattern per line, blank lines and lines starting with # are ignored
+    fn parse_patterns(text: &str) -> Vec<String> {
+        text.lines()
+            .filter(|line| {
+                let line = line.trim_start();
+                !line.is_empty() && !line.starts_with('#')
+            })
+            .map(String::from)
+            .collect()
+    }
+
+    // a --flag and its --no-flag, None when neither is given
+    fn flag(on: bool, off: bool) -> Option<bool> {
+        match (on, off) {
+            (true, _) => Some(true),
+            (_, true) => Some(false),
+            _ => None,
+        }
+    }
+
+    // true when the contents of the files need to be grep'ed
+    pub fn has_patterns(&self) -> bool {
+        !(self.fixed_strings.is_empty()
+            && self.regex_patterns.is_empty()
+            && self.not_fixed_strings.is_empty()
+            && self.not_regex_patterns.is_empty()
+            && self.none_of_patterns.is_empty())
+    }
+
+    fn use_colour(mode: ColourMode) -> bool {
+        match mode {
+            ColourMode::Always => true,
+            ColourMode::Never => false,
+            ColourMode::Auto => {
+                // see https://no-color.org/
+                let no_color = env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+                !no_color && io::stdout().is_terminal()
+            }
+        }
+    }
+
+    fn number_of_threads(threads_opt: Option<usize>) -> Result<usize> {
+        match threads_opt {
+            Some(0) => {
+                Err(SfindError::NoThreads)
+            }
+            Some(threads) => {
+                Ok(threads)
+            }
+            None => {
+                Ok(std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1))
+            }
+        }
+    }
+
     fn parse_times(time_opt: &Option<String>) -> Result<(Option<SystemTime>, Option<SystemTime>)> {
         match time_opt {
             None => {
@@ -176,7 +561,7 @@ impl CommandOptions {
                         Ok((Some(Self::parse_time(v[0])?), Some(Self::parse_time(v[1])?)))
                     }
                     _ => {
-                        Err(anyhow!("Too many times"))
+                        Err(SfindError::BadTimeSpec { spec: time_str.clone(), reason: "too many times" })
                     }
                 }
             }
@@ -184,12 +569,15 @@ impl CommandOptions {
     }
 
     fn parse_time(time_str: &str) -> Result<SystemTime> {
-        if time_str.len() == 0 {
-            return Err(anyhow!("blank time string"))
+        if time_str.is_empty() {
+            return Err(SfindError::BadTimeSpec { spec: time_str.to_string(), reason: "blank time string" })
         }
         macro_rules! time_error {
             () => {
-                Err(anyhow!("expecting 0-9 followed by s, m, h or d"))
+                Err(SfindError::BadTimeSpec {
+                    spec: time_str.to_string(),
+                    reason: "expecting 0-9 followed by s, m, h or d",
+                })
             };
         }
         macro_rules! check_scale {
@@ -241,12 +629,15 @@ impl CommandOptions {
                 Ok(None)
             }
             Some(size_str) => {
-                if size_str.len() == 0 {
-                    return Err(anyhow!("blank size string"))
+                if size_str.is_empty() {
+                    return Err(SfindError::BadSizeSpec { spec: size_str.clone(), reason: "blank size string" })
                 }
                 macro_rules! size_error {
                     () => {
-                        Err(anyhow!("expecting 0-9 followed by k, m, g, t"))
+                        Err(SfindError::BadSizeSpec {
+                            spec: size_str.clone(),
+                            reason: "expecting 0-9 followed by k, m, g, t",
+                        })
                     };
                 }
                 macro_rules! check_scale {
@@ -293,3 +684,50 @@ impl CommandOptions {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::ScratchFolder;
+
+    #[test]
+    fn parse_patterns() {
+        let text = "# deprecated\nold_api\n\n  \n  # indented comment\nlegacy::call\r\nx # y\n";
+        assert_eq!(CommandOptions::parse_patterns(text), vec!["old_api", "legacy::call", "x # y"]);
+    }
+
+    #[test]
+    fn parse_encoding() {
+        let parse = |name: &str| CommandOptions::parse_encoding(&Some(name.to_string()));
+        assert_eq!(parse("auto").unwrap(), None);
+        assert_eq!(parse("UTF-16LE").unwrap(), Some(encoding_rs::UTF_16LE));
+        assert_eq!(parse("latin1").unwrap(), Some(encoding_rs::WINDOWS_1252));
+        assert!(matches!(parse("utf-42"), Err(SfindError::UnknownEncoding(_))));
+    }
+
+    #[test]
+    fn bad_file_glob() {
+        let args = |glob: &str| vec!["sfind".to_string(), glob.to_string()];
+        assert!(CommandOptions::new(&args("[a-z]*.rs")).is_ok());
+        let error = CommandOptions::new(&args("[z-a]")).unwrap_err();
+        assert!(matches!(error, SfindError::BadPattern { .. }));
+    }
+
+    #[test]
+    fn no_patterns() {
+        let folder = ScratchFolder::new("no-patterns");
+        let empty = folder.write("empty.txt", "# only a comment\n");
+        let args: Vec<String> = ["sfind", "--fixed-file", empty.to_str().unwrap()].map(String::from).to_vec();
+        let error = CommandOptions::new(&args).unwrap_err();
+        assert!(matches!(&error, SfindError::NoPatterns(paths) if *paths == [empty.as_path()]));
+        assert_eq!(error.to_string(), format!("no patterns to find in {}", empty.display()));
+    }
+
+    #[test]
+    fn no_threads() {
+        let args: Vec<String> = ["sfind", "--threads", "0"].map(String::from).to_vec();
+        assert!(matches!(CommandOptions::new(&args), Err(SfindError::NoThreads)));
+        let args: Vec<String> = ["sfind", "--no-such-option"].map(String::from).to_vec();
+        assert!(matches!(CommandOptions::new(&args), Err(SfindError::Usage(_))));
+    }
+}
@@ -1,16 +1,43 @@
-use anyhow::{anyhow, Result};
 use cfg_if;
 use serde;
 use serde_json;
+use std::collections::HashMap;
 use std::fs;
 use std::fs::File;
 use std::io::prelude::*;
 use std::path::PathBuf;
 
+use crate::colours::{ColourTheme, Colours};
+use crate::error::SfindError;
+
+type Result<T> = std::result::Result<T, SfindError>;
+
 #[derive(serde::Deserialize, Debug)]
 pub struct ConfigJson {
     pub folders_to_prune: Vec<String>,
     pub files_to_prune: Vec<String>,
+    #[serde(default)]
+    pub colours: ColourTheme,
+    #[serde(default = "default_group_separator")]
+    pub group_separator: String,
+    #[serde(default)]
+    pub match_defaults: MatchDefaults,
+    // the encoding label to read files with an extension in, keyed by the extension
+    #[serde(default)]
+    pub encodings: HashMap<String, String>,
+}
+
+// defaults for the options that change how patterns match
+#[derive(serde::Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct MatchDefaults {
+    pub smart_case: bool,
+    pub word_regexp: bool,
+    pub line_regexp: bool,
+}
+
+fn default_group_separator() -> String {
+    "--".to_string()
 }
 
 #[derive(Debug)]
@@ -22,7 +49,21 @@ pub struct AppConfig {
 
 static DEFAULT_CONFIG_JSON: &str = r#"{
     "folders_to_prune": [".svn", ".git", ".hg", "target"],
-    "files_to_prune":   ["*~"]
+    "files_to_prune":   ["*~"],
+    "colours": {
+        "file":         "magenta",
+        "line_number":  "green",
+        "separator":    "",
+        "context":      "",
+        "matches":      ["bold red", "yellow", "bold blue", "green", "magenta"]
+    },
+    "group_separator": "--",
+    "match_defaults": {
+        "smart_case":   false,
+        "word_regexp":  false,
+        "line_regexp":  false
+    },
+    "encodings": {}
 }
 "#;
 
@@ -31,31 +72,41 @@ impl AppConfig {
         let config_path = config_file_path(app_name)?;
 
         let config_data = if config_path.exists() {
-            fs::read_to_string(&config_path).map_err(|e| {
-                anyhow!(
-                    "Error reading {} - {}",
-                    &config_path.display(),
-                    e.to_string()
-                )
-            })?
+            fs::read_to_string(&config_path).map_err(|e| SfindError::io(&config_path, e))?
         } else {
             DEFAULT_CONFIG_JSON.to_string()
         };
 
+        let config: ConfigJson = serde_json::from_str(&config_data).map_err(|e| SfindError::BadConfig {
+            path: config_path.clone(),
+            message: e.to_string(),
+        })?;
+        for (extension, label) in &config.encodings {
+            if encoding_rs::Encoding::for_label(label.as_bytes()).is_none() {
+                return Err(SfindError::BadConfig {
+                    path: config_path.clone(),
+                    message: format!("encodings - unknown encoding {:?} for {:?}", label, extension),
+                });
+            }
+        }
+
         let app_config = AppConfig {
             app_name: app_name.to_string(),
-            config_path: config_path.clone(),
-            config: serde_json::from_str(&config_data).map_err(|e| {
-                anyhow!(
-                    "Error parsing config {} - {}",
-                    &config_path.display(),
-                    e.to_string()
-                )
-            })?,
+            config_path,
+            config,
         };
         Ok(app_config)
     }
 
+    // the colours are only checked when they are used so that a bad colour
+    // does not stop --show-config or --color=never from working
+    pub fn colours(&self) -> Result<Colours> {
+        Colours::new(&self.config.colours).map_err(|message| SfindError::BadConfig {
+            path: self.config_path.clone(),
+            message: format!("colours - {}", message),
+        })
+    }
+
     pub fn config_file_path(&self) -> Result<PathBuf> {
         config_file_path(&self.app_name)
     }
@@ -64,15 +115,12 @@ impl AppConfig {
         let config_path = self.config_file_path()?;
 
         if config_path.exists() {
-            return Err(anyhow!(
-                "config file already exists: {}",
-                config_path.display()
-            ));
+            return Err(SfindError::ConfigExists(config_path));
         }
 
         println!("Saving default config in {}", config_path.display());
-        let mut f = File::create(config_path)?;
-        f.write_all(DEFAULT_CONFIG_JSON.as_bytes())?;
+        let mut f = File::create(&config_path).map_err(|e| SfindError::io(&config_path, e))?;
+        f.write_all(DEFAULT_CONFIG_JSON.as_bytes()).map_err(|e| SfindError::io(&config_path, e))?;
         Ok(())
     }
 
@@ -89,6 +137,28 @@ impl AppConfig {
         for filename in &self.config.files_to_prune {
             println!("    {}", filename);
         }
+        println!("colours:");
+        let theme = &self.config.colours;
+        println!("    file:        {:?}", theme.file);
+        println!("    line_number: {:?}", theme.line_number);
+        println!("    separator:   {:?}", theme.separator);
+        println!("    context:     {:?}", theme.context);
+        println!("    matches:     {:?}", theme.matches);
+        if let Err(message) = Colours::new(theme) {
+            println!("    error:       {}", message);
+        }
+        println!("group separator: {:?}", self.config.group_separator);
+        println!("match defaults:");
+        let defaults = &self.config.match_defaults;
+        println!("    smart_case:  {}", defaults.smart_case);
+        println!("    word_regexp: {}", defaults.word_regexp);
+        println!("    line_regexp: {}", defaults.line_regexp);
+        println!("encodings:");
+        let mut encodings: Vec<_> = self.config.encodings.iter().collect();
+        encodings.sort();
+        for (extension, label) in encodings {
+            println!("    {:12} {}", extension, label);
+        }
     }
 }
 
@@ -97,7 +167,8 @@ cfg_if::cfg_if! {
         use std::env;
 
         fn config_file_path(app_name: &str) -> Result<PathBuf> {
-            let home_dir = env::var("HOME")?;
+            let home_dir = env::var("HOME")
+                .map_err(|e| SfindError::NoConfigFolder(format!("HOME - {}", e)))?;
             Ok(PathBuf::from(
                 format!("{}/Library/Preferences/{}.json", home_dir, &app_name)))
         }
@@ -119,12 +190,12 @@ cfg_if::cfg_if! {
                     path_buf.as_mut_ptr())
             };
             if hresult != S_OK {
-                Err(anyhow!("SHGetFolderPathW failed {}", hresult))
+                Err(SfindError::NoConfigFolder(format!("SHGetFolderPathW failed {}", hresult)))
             } else {
                 use std::ffi::OsString;
                 use std::os::windows::ffi::OsStringExt as _;
                 let Some(length) = path_buf.iter().position(|&ch| ch == 0)
-                    else { return Err(anyhow!("missing 0 in SHGetFolderPathW buffer")); };
+                    else { return Err(SfindError::NoConfigFolder("missing 0 in SHGetFolderPathW buffer".to_string())); };
                 Ok(OsString::from_wide(&path_buf[0..length]).into())
             }
         }
@@ -139,10 +210,11 @@ cfg_if::cfg_if! {
         use xdg;
 
         fn config_file_path(app_name: &str) -> Result<PathBuf> {
-            let xdg_dirs = xdg::BaseDirectories::new()?;
+            let xdg_dirs = xdg::BaseDirectories::new()
+                .map_err(|e| SfindError::NoConfigFolder(e.to_string()))?;
 
-            Ok(xdg_dirs.place_config_file(
-                    format!("{}.json", &app_name))?)
+            xdg_dirs.place_config_file(format!("{}.json", &app_name))
+                .map_err(|e| SfindError::NoConfigFolder(e.to_string()))
         }
     }
 }
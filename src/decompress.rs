@@ -0,0 +1,139 @@
+use std::io::{self, Read};
+
+// the number of bytes at the start of a file that from_magic needs to see
+pub const MAGIC_LEN: usize = 10;
+
+// after "BZh" and the block size, a bzip2 stream starts with the magic of its
+// first block, or the end of stream magic when it is empty
+const BZIP2_BLOCK_MAGIC: &[u8] = &[0x31, 0x41, 0x59, 0x26, 0x53, 0x59];
+const BZIP2_END_MAGIC: &[u8] = &[0x17, 0x72, 0x45, 0x38, 0x50, 0x90];
+
+// The compression formats that --decompress can search inside
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Compression {
+    Gzip,
+    Xz,
+    Bzip2,
+    Zstd,
+}
+
+impl Compression {
+    // the compression of a file from the magic bytes at its start
+    pub fn from_magic(start: &[u8]) -> Option<Compression> {
+        if start.starts_with(&[0x1f, 0x8b]) {
+            Some(Compression::Gzip)
+        } else if start.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+            Some(Compression::Xz)
+        } else if Self::is_bzip2(start) {
+            Some(Compression::Bzip2)
+        } else if start.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Some(Compression::Zstd)
+        } else {
+            None
+        }
+    }
+
+    // "BZh" alone is too common at the start of a text file to go on
+    fn is_bzip2(start: &[u8]) -> bool {
+        start.len() >= MAGIC_LEN
+            && start.starts_with(b"BZh")
+            && (b'1'..=b'9').contains(&start[3])
+            && (&start[4..MAGIC_LEN] == BZIP2_BLOCK_MAGIC || &start[4..MAGIC_LEN] == BZIP2_END_MAGIC)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Compression::Gzip => "gzip",
+            Compression::Xz => "xz",
+            Compression::Bzip2 => "bzip2",
+            Compression::Zstd => "zstd",
+        }
+    }
+
+    // Read the decompressed contents of file, None when the decoder rejects the
+    // header of a file that only starts like a compressed file. A file with a good
+    // header and bad data after it is an error.
+    pub fn checked_decoder<'a, R: Read + 'a>(&self, file: R) -> io::Result<Option<Box<dyn Read + 'a>>> {
+        match self {
+            Compression::Gzip => {
+                // the header is read with the first of the contents
+                let mut decoder = flate2::read::MultiGzDecoder::new(file);
+                let mut first = vec![];
+                match (&mut decoder).take(1).read_to_end(&mut first) {
+                    Ok(_) => Ok(Some(Box::new(io::Cursor::new(first).chain(decoder)))),
+                    Err(_) if decoder.header().is_none() => Ok(None),
+                    Err(e) => Err(e),
+                }
+            }
+            Compression::Zstd => match ruzstd::decoding::StreamingDecoder::new(file) {
+                Ok(decoder) => Ok(Some(Box::new(decoder))),
+                Err(_) => Ok(None),
+            },
+            // the magic of bzip2 and xz is long enough to go on
+            Compression::Bzip2 | Compression::Xz => self.decoder(file).map(Some),
+        }
+    }
+
+    // read the decompressed contents of file
+    pub fn decoder<'a, R: Read + 'a>(&self, file: R) -> io::Result<Box<dyn Read + 'a>> {
+        let invalid_data = |e: String| io::Error::new(io::ErrorKind::InvalidData, e);
+        match self {
+            // rotated logs may be several gzip members one after the other
+            Compression::Gzip => Ok(Box::new(flate2::read::MultiGzDecoder::new(file))),
+            Compression::Bzip2 => Ok(Box::new(bzip2::read::MultiBzDecoder::new(file))),
+            Compression::Zstd => {
+                let decoder = ruzstd::decoding::StreamingDecoder::new(file).map_err(|e| invalid_data(e.to_string()))?;
+                Ok(Box::new(decoder))
+            }
+            // xz files may also be several streams one after the other
+            Compression::Xz => Ok(Box::new(lzma_rust2::XzReader::new(file, true))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn magic_bytes() {
+        assert_eq!(Compression::from_magic(b"\x1f\x8b\x08\x00"), Some(Compression::Gzip));
+        assert_eq!(Compression::from_magic(b"\xfd7zXZ\x00\x00"), Some(Compression::Xz));
+        assert_eq!(Compression::from_magic(b"BZh91AY&SY"), Some(Compression::Bzip2));
+        assert_eq!(Compression::from_magic(b"BZh9\x17\x72\x45\x38\x50\x90"), Some(Compression::Bzip2));
+        assert_eq!(Compression::from_magic(b"BZh is how it starts"), None);
+        assert_eq!(Compression::from_magic(b"BZh91AY"), None);
+        assert_eq!(Compression::from_magic(b"\x28\xb5\x2f\xfd\x04"), Some(Compression::Zstd));
+        assert_eq!(Compression::from_magic(b"plain text"), None);
+        assert_eq!(Compression::from_magic(b"\x1f"), None);
+    }
+
+    #[test]
+    fn gzip_members() {
+        let mut compressed = vec![];
+        for text in ["first line\n", "second line\n"] {
+            let mut encoder = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+            encoder.write_all(text.as_bytes()).unwrap();
+            compressed.extend(encoder.finish().unwrap());
+        }
+        let mut contents = String::new();
+        Compression::Gzip.decoder(&compressed[..]).unwrap().read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, "first line\nsecond line\n");
+    }
+
+    #[test]
+    fn rejected_headers() {
+        // the gzip magic followed by text is read as it is
+        assert!(Compression::Gzip.checked_decoder(&b"\x1f\x8b\x08garbage"[..]).unwrap().is_none());
+        // a good header with bad data after it is an error
+        assert!(Compression::Gzip.checked_decoder(&b"\x1f\x8b\x08\0\0\0\0\0\0\x03garbage"[..]).is_err());
+
+        let mut encoder = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+        encoder.write_all(b"text").unwrap();
+        let compressed = encoder.finish().unwrap();
+        let mut contents = String::new();
+        Compression::Gzip.checked_decoder(&compressed[..]).unwrap().unwrap().read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, "text");
+    }
+}
@@ -0,0 +1,205 @@
+use std::io::Write;
+use std::path::PathBuf;
+
+use encoding_rs::Encoding;
+
+use crate::error::SfindError;
+use crate::file_encoding;
+
+// lines of unchanged context around each change
+const CONTEXT_LINES: usize = 3;
+
+// The old lines first..first + old_count of a file are replaced by new_lines
+#[derive(Debug, PartialEq)]
+pub struct LineChange {
+    pub first: usize,
+    pub old_count: usize,
+    pub new_lines: Vec<String>,
+}
+
+// the text of the file after the changes
+pub fn apply_changes(old_lines: &[&str], no_newline_at_end: bool, changes: &[LineChange]) -> String {
+    let mut new_lines: Vec<&str> = vec![];
+    let mut line_index = 0;
+    for change in changes {
+        new_lines.extend_from_slice(&old_lines[line_index..change.first]);
+        new_lines.extend(change.new_lines.iter().map(|line| line.as_str()));
+        line_index = change.first + change.old_count;
+    }
+    new_lines.extend_from_slice(&old_lines[line_index..]);
+
+    let mut text = new_lines.join("\n");
+    if !no_newline_at_end && !new_lines.is_empty() {
+        text.push('\n');
+    }
+    text
+}
+
+// hunk ranges are 1 based, an empty range names the line before it
+fn hunk_range(start: usize, len: usize) -> String {
+    if len == 0 {
+        format!("{},0", start)
+    } else {
+        format!("{},{}", start + 1, len)
+    }
+}
+
+// Write the changes to a file as a unified diff that git apply and patch understand.
+// The lines are written in the encoding of the file so that they match what is in it.
+// The changes must be in order and must not overlap.
+pub fn write_unified_diff<W: Write>(
+    out: &mut W,
+    path: &str,
+    old_lines: &[&str],
+    no_newline_at_end: bool,
+    changes: &[LineChange],
+    encoding: &'static Encoding,
+) -> Result<(), SfindError> {
+    if changes.is_empty() {
+        return Ok(());
+    }
+    let bad_encoding = |reason| SfindError::Replace { path: PathBuf::from(path), reason };
+    // the lines of a diff must be \n separated bytes, which UTF-16 lines are not
+    if !encoding.is_ascii_compatible() {
+        return Err(bad_encoding(format!("cannot write a diff of text encoded as {}", encoding.name())));
+    }
+
+    // the whole diff is made first so that nothing is written when a line cannot be encoded
+    let mut diff = vec![];
+    let path = path.strip_prefix("./").unwrap_or(path);
+    writeln!(diff, "--- a/{}\n+++ b/{}", path, path).map_err(SfindError::Output)?;
+
+    let last_old_line = old_lines.len().saturating_sub(1);
+    let write_line = |diff: &mut Vec<u8>, prefix: u8, line: &str, is_last: bool| -> Result<(), SfindError> {
+        diff.push(prefix);
+        diff.extend(file_encoding::encode_text(line, encoding).map_err(bad_encoding)?);
+        diff.push(b'\n');
+        if is_last && no_newline_at_end {
+            diff.extend_from_slice(b"\\ No newline at end of file\n");
+        }
+        Ok(())
+    };
+
+    // how many more lines the new file has before the current hunk
+    let mut delta: isize = 0;
+    let mut index = 0;
+    while index < changes.len() {
+        // changes that are close enough share a hunk
+        let mut hunk_end = index + 1;
+        while hunk_end < changes.len() {
+            let prev = &changes[hunk_end - 1];
+            if changes[hunk_end].first > prev.first + prev.old_count + 2 * CONTEXT_LINES {
+                break;
+            }
+            hunk_end += 1;
+        }
+        let hunk = &changes[index..hunk_end];
+        let last = &hunk[hunk.len() - 1];
+
+        let old_start = hunk[0].first.saturating_sub(CONTEXT_LINES);
+        let old_end = (last.first + last.old_count + CONTEXT_LINES).min(old_lines.len());
+        let hunk_delta: isize = hunk
+            .iter()
+            .map(|change| change.new_lines.len() as isize - change.old_count as isize)
+            .sum();
+        let new_start = (old_start as isize + delta) as usize;
+        let new_len = ((old_end - old_start) as isize + hunk_delta) as usize;
+        writeln!(
+            diff,
+            "@@ -{} +{} @@",
+            hunk_range(old_start, old_end - old_start),
+            hunk_range(new_start, new_len)
+        )
+        .map_err(SfindError::Output)?;
+
+        let mut line_index = old_start;
+        for change in hunk {
+            let change_end = change.first + change.old_count;
+            for (offset, line) in old_lines[line_index..change.first].iter().enumerate() {
+                write_line(&mut diff, b' ', line, line_index + offset == last_old_line)?;
+            }
+            for (offset, line) in old_lines[change.first..change_end].iter().enumerate() {
+                write_line(&mut diff, b'-', line, change.first + offset == last_old_line)?;
+            }
+            let ends_file = change_end == old_lines.len();
+            for (offset, line) in change.new_lines.iter().enumerate() {
+                let is_last = ends_file && offset + 1 == change.new_lines.len();
+                write_line(&mut diff, b'+', line, is_last)?;
+            }
+            line_index = change_end;
+        }
+        for (offset, line) in old_lines[line_index..old_end].iter().enumerate() {
+            write_line(&mut diff, b' ', line, line_index + offset == last_old_line)?;
+        }
+
+        delta += hunk_delta;
+        index = hunk_end;
+    }
+    out.write_all(&diff).map_err(SfindError::Output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use encoding_rs::{UTF_16LE, UTF_8, WINDOWS_1252};
+
+    fn change(first: usize, old_count: usize, new_lines: &[&str]) -> LineChange {
+        LineChange { first, old_count, new_lines: new_lines.iter().map(|line| line.to_string()).collect() }
+    }
+
+    fn diff(old_lines: &[&str], no_newline_at_end: bool, changes: &[LineChange]) -> String {
+        let mut out = vec![];
+        write_unified_diff(&mut out, "./src/x.rs", old_lines, no_newline_at_end, changes, UTF_8).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn apply_changes_to_lines() {
+        let old_lines = ["a", "foo", "b"];
+        let changes = [change(1, 1, &["bar", "baz"]), change(2, 1, &[])];
+        assert_eq!(apply_changes(&old_lines, false, &changes), "a\nbar\nbaz\n");
+        assert_eq!(apply_changes(&old_lines, true, &changes[..1]), "a\nbar\nbaz\nb");
+    }
+
+    #[test]
+    fn separate_hunks() {
+        let old_lines: Vec<_> = (1..=20).map(|n| if n == 2 || n == 18 { "foo" } else { "-" }).collect();
+        let changes = [change(1, 1, &["bar"]), change(17, 1, &["bar", "baz"])];
+        assert_eq!(
+            diff(&old_lines, false, &changes),
+            concat!(
+                "--- a/src/x.rs\n+++ b/src/x.rs\n",
+                "@@ -1,5 +1,5 @@\n -\n-foo\n+bar\n -\n -\n -\n",
+                "@@ -15,6 +15,7 @@\n -\n -\n -\n-foo\n+bar\n+baz\n -\n -\n",
+            )
+        );
+    }
+
+    #[test]
+    fn merged_hunk_without_newline_at_end() {
+        let old_lines = ["a", "foo", "b", "c", "foo"];
+        let changes = [change(1, 1, &["bar"]), change(4, 1, &["bar"])];
+        assert_eq!(
+            diff(&old_lines, true, &changes),
+            concat!(
+                "--- a/src/x.rs\n+++ b/src/x.rs\n",
+                "@@ -1,5 +1,5 @@\n a\n-foo\n+bar\n b\n c\n-foo\n",
+                "\\ No newline at end of file\n+bar\n\\ No newline at end of file\n",
+            )
+        );
+    }
+
+    #[test]
+    fn diff_in_file_encoding() {
+        let old_lines = ["café key", "x"];
+        let changes = [change(0, 1, &["café value"])];
+        let mut out = vec![];
+        write_unified_diff(&mut out, "x.txt", &old_lines, false, &changes, WINDOWS_1252).unwrap();
+        let expected: &[u8] = b"--- a/x.txt\n+++ b/x.txt\n@@ -1,2 +1,2 @@\n-caf\xe9 key\n+caf\xe9 value\n x\n";
+        assert_eq!(out, expected);
+
+        let mut out = vec![];
+        assert!(write_unified_diff(&mut out, "x.txt", &old_lines, false, &changes, UTF_16LE).is_err());
+        assert!(out.is_empty());
+    }
+}
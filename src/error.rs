@@ -0,0 +1,82 @@
+use std::io;
+use std::path::PathBuf;
+
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum SfindError {
+    #[error("{} - {source}", .path.display())]
+    Io {
+        path: PathBuf,
+        source: io::Error,
+    },
+
+    #[error("filename is not utf-8 {}", .0.display())]
+    NonUtf8Path(PathBuf),
+
+    #[error("bad pattern {pattern} - {source}")]
+    BadPattern {
+        pattern: String,
+        source: regex::Error,
+    },
+
+    #[error("bad config {} - {message}", .path.display())]
+    BadConfig {
+        path: PathBuf,
+        message: String,
+    },
+
+    #[error("config file already exists: {}", .0.display())]
+    ConfigExists(PathBuf),
+
+    #[error("cannot find the config folder - {0}")]
+    NoConfigFolder(String),
+
+    #[error("bad time {spec:?} - {reason}")]
+    BadTimeSpec {
+        spec: String,
+        reason: &'static str,
+    },
+
+    #[error("bad size {spec:?} - {reason}")]
+    BadSizeSpec {
+        spec: String,
+        reason: &'static str,
+    },
+
+    // the command line could not be parsed, or is --help or --version
+    #[error(transparent)]
+    Usage(#[from] clap::Error),
+
+    #[error("missing progname in command arguments")]
+    MissingProgname,
+
+    #[error("--threads must be at least 1")]
+    NoThreads,
+
+    #[error("no patterns to find in {}", display_paths(.0))]
+    NoPatterns(Vec<PathBuf>),
+
+    #[error("unknown encoding {0:?}")]
+    UnknownEncoding(String),
+
+    #[error("cannot replace in {} - {reason}", .path.display())]
+    Replace {
+        path: PathBuf,
+        reason: String,
+    },
+
+    #[error("error writing output - {0}")]
+    Output(io::Error),
+}
+
+fn display_paths(paths: &[PathBuf]) -> String {
+    let paths: Vec<_> = paths.iter().map(|path| path.display().to_string()).collect();
+    paths.join(", ")
+}
+
+impl SfindError {
+    pub fn io(path: &std::path::Path, source: io::Error) -> SfindError {
+        SfindError::Io { path: path.to_path_buf(), source }
+    }
+}
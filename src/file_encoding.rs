@@ -0,0 +1,213 @@
+use std::borrow::Cow;
+use std::io::{self, BufRead, BufReader, Read};
+
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE, UTF_8, WINDOWS_1252};
+
+// Decode the text of a file. Without a known encoding the text is UTF-8,
+// falling back to Windows-1252 when it is not valid UTF-8.
+// Returns the text and the encoding it was decoded from.
+pub fn decode_text<'b>(bytes: &'b [u8], encoding: Option<&'static Encoding>) -> (Cow<'b, str>, &'static Encoding) {
+    if let Some(encoding) = encoding {
+        let (cow_text, _had_errors) = encoding.decode_with_bom_removal(bytes);
+        return (cow_text, encoding);
+    }
+
+    let (cow_utf8, encoding_used, had_errors) = UTF_8.decode(bytes);
+    if !had_errors {
+        (cow_utf8, encoding_used)
+    } else {
+        let (cow_1252, encoding_used, _had_errors) = WINDOWS_1252.decode(bytes);
+        (cow_1252, encoding_used)
+    }
+}
+
+// the length of the byte order mark at the start of the file
+pub fn bom_len(bytes: &[u8]) -> u64 {
+    Encoding::for_bom(bytes).map_or(0, |(_, bom_len)| bom_len as u64)
+}
+
+// the number of bytes text takes up in the file
+fn encoded_len(encoding: &'static Encoding, text: &str) -> u64 {
+    let len = if encoding == UTF_8 {
+        text.len()
+    } else if encoding == UTF_16LE || encoding == UTF_16BE {
+        text.encode_utf16().count() * 2
+    } else if encoding.is_single_byte() {
+        text.chars().count()
+    } else {
+        encoding.encode(text).0.len()
+    };
+    len as u64
+}
+
+// the offset in the file of index into text, when the text starts at text_offset in the file
+pub fn file_offset(encoding: &'static Encoding, text_offset: u64, text: &str, index: usize) -> u64 {
+    text_offset + encoded_len(encoding, &text[..index])
+}
+
+// The offsets in the file of indexes into the decoded text of the whole file.
+// Each offset is counted on from the last one so that the text between them is
+// only counted once, rather than counting from the start of the file each time.
+pub struct TextOffsets<'t> {
+    encoding: &'static Encoding,
+    text: &'t str,
+    index: usize,
+    offset: u64,
+}
+
+impl<'t> TextOffsets<'t> {
+    pub fn new(encoding: &'static Encoding, text: &'t str, text_offset: u64) -> TextOffsets<'t> {
+        TextOffsets { encoding, text, index: 0, offset: text_offset }
+    }
+
+    pub fn offset(&mut self, index: usize) -> u64 {
+        if index >= self.index {
+            self.offset += encoded_len(self.encoding, &self.text[self.index..index]);
+        } else {
+            self.offset -= encoded_len(self.encoding, &self.text[index..self.index]);
+        }
+        self.index = index;
+        self.offset
+    }
+}
+
+// Put the text back into the encoding it was decoded from.
+// encoding_rs only encodes UTF-16 as UTF-8 so that is done here.
+pub fn encode_text(text: &str, encoding: &'static Encoding) -> Result<Vec<u8>, String> {
+    if encoding == UTF_16LE {
+        return Ok(text.encode_utf16().flat_map(|unit| unit.to_le_bytes()).collect());
+    }
+    if encoding == UTF_16BE {
+        return Ok(text.encode_utf16().flat_map(|unit| unit.to_be_bytes()).collect());
+    }
+    if encoding.output_encoding() != encoding {
+        return Err(format!("cannot write text encoded as {}", encoding.name()));
+    }
+
+    let (encoded, _encoding_used, unmappable) = encoding.encode(text);
+    if unmappable {
+        return Err(format!("the replacement cannot be written as {}", encoding.name()));
+    }
+    Ok(encoded.into_owned())
+}
+
+// The lines of a file as text with the offset in the file of the start of each line.
+// Encodings like UTF-16 that do not keep the bytes of \n as they are in ASCII
+// are decoded all at once, the others a line at a time.
+pub enum FileLines<R: Read> {
+    Reader {
+        reader: BufReader<R>,
+        encoding: Option<&'static Encoding>,
+        next_offset: u64,
+    },
+    Decoded {
+        text: String,
+        encoding: &'static Encoding,
+        next_index: usize,
+        next_offset: u64,
+    },
+}
+
+impl<R: Read> FileLines<R> {
+    pub fn new(mut file: R, encoding: Option<&'static Encoding>) -> io::Result<FileLines<R>> {
+        match encoding {
+            Some(encoding) if !encoding.is_ascii_compatible() => {
+                let mut bytes = vec![];
+                file.read_to_end(&mut bytes)?;
+                let (text, _) = decode_text(&bytes, Some(encoding));
+                Ok(FileLines::Decoded {
+                    text: text.into_owned(),
+                    encoding,
+                    next_index: 0,
+                    next_offset: bom_len(&bytes),
+                })
+            }
+            _ => Ok(FileLines::Reader {
+                reader: BufReader::with_capacity(1024 * 1024, file),
+                encoding,
+                next_offset: 0,
+            }),
+        }
+    }
+
+    // the offset, text without the \n and encoding of the next line
+    pub fn next_line(&mut self) -> io::Result<Option<(u64, String, &'static Encoding)>> {
+        let (line_offset, mut line, encoding) = match self {
+            FileLines::Reader { reader, encoding, next_offset } => {
+                let mut line_buf = vec![];
+                let len = reader.read_until(0x0a, &mut line_buf)?;
+                if len == 0 {
+                    return Ok(None);
+                }
+                // the offset of the text of the line, after any byte order mark
+                let line_offset = if *next_offset == 0 { bom_len(&line_buf) } else { *next_offset };
+                *next_offset += len as u64;
+
+                let (cow_line, encoding_used) = decode_text(&line_buf, *encoding);
+                (line_offset, cow_line.into_owned(), encoding_used)
+            }
+            FileLines::Decoded { text, encoding, next_index, next_offset } => {
+                if *next_index >= text.len() {
+                    return Ok(None);
+                }
+                let end = text[*next_index..].find('\n').map_or(text.len(), |index| *next_index + index + 1);
+                let line = &text[*next_index..end];
+                let line_offset = *next_offset;
+                *next_offset = file_offset(encoding, line_offset, line, line.len());
+                *next_index = end;
+                (line_offset, line.to_string(), *encoding)
+            }
+        };
+
+        if line.ends_with('\n') {
+            line.truncate(line.len() - 1);
+        }
+        Ok(Some((line_offset, line, encoding)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utf16le(text: &str) -> Vec<u8> {
+        let mut bytes = vec![0xff, 0xfe];
+        bytes.extend(encode_text(text, UTF_16LE).unwrap());
+        bytes
+    }
+
+    #[test]
+    fn offsets_in_file() {
+        assert_eq!(file_offset(UTF_8, 3, "café x", 6), 9);
+        assert_eq!(file_offset(WINDOWS_1252, 0, "café x", 6), 5);
+        assert_eq!(file_offset(UTF_16LE, 2, "café x", 6), 12);
+    }
+
+    #[test]
+    fn running_offsets() {
+        let text = "café\nx é\ny";
+        let mut offsets = TextOffsets::new(UTF_16LE, text, 2);
+        assert_eq!(offsets.offset(6), 12);
+        assert_eq!(offsets.offset(text.len()), file_offset(UTF_16LE, 2, text, text.len()));
+        assert_eq!(offsets.offset(3), 8);
+        assert_eq!(offsets.offset(0), 2);
+    }
+
+    #[test]
+    fn utf16_lines() {
+        let bytes = utf16le("first\nsecond é\n");
+        let mut lines = FileLines::new(&bytes[..], Some(UTF_16LE)).unwrap();
+        assert_eq!(lines.next_line().unwrap(), Some((2, "first".to_string(), UTF_16LE)));
+        assert_eq!(lines.next_line().unwrap(), Some((14, "second é".to_string(), UTF_16LE)));
+        assert_eq!(lines.next_line().unwrap(), None);
+    }
+
+    #[test]
+    fn fallback_lines() {
+        let bytes = b"caf\xc3\xa9\ncaf\xe9\n";
+        let mut lines = FileLines::new(&bytes[..], None).unwrap();
+        assert_eq!(lines.next_line().unwrap(), Some((0, "café".to_string(), UTF_8)));
+        assert_eq!(lines.next_line().unwrap(), Some((6, "café".to_string(), WINDOWS_1252)));
+        assert_eq!(lines.next_line().unwrap(), None);
+    }
+}
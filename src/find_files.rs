@@ -1,6 +1,8 @@
 use std::collections::VecDeque;
 use std::fs::{self, DirEntry, Metadata};
 use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::SystemTime;
 
 use cfg_if;
 
@@ -8,6 +10,10 @@ use regex::{Regex, RegexBuilder};
 
 pub use crate::command_options::CommandOptions;
 pub use crate::config_json::ConfigJson;
+pub use crate::ignore_files::IgnoreDir;
+use crate::archive::{ArchiveKind, ArchiveMember, ArchiveReader, MEMBER_SIZE_LIMIT};
+use crate::error::SfindError;
+use crate::glob::{glob_to_regex_pattern, GlobSyntax};
 
 cfg_if::cfg_if! {
     if #[cfg(target_os = "windows")] {
@@ -24,31 +30,152 @@ cfg_if::cfg_if! {
 struct PathToScan {
     pub path: PathBuf,
     pub depth: usize,
+    pub parent_ignore: Option<Arc<IgnoreDir>>,
 }
 
-pub struct FindFiles<'caller> {
+// The folders waiting to be scanned, shared by all the FindFiles
+// iterators of a parallel search.
+pub struct FolderQueue {
+    state: Mutex<FolderQueueState>,
+    folder_pushed: Condvar,
+}
+
+struct FolderQueueState {
     folders: VecDeque<PathToScan>,
+    // number of iterators that are scanning a folder and may push more folders
+    busy: usize,
+}
+
+// A file found by FindFiles
+#[derive(Debug)]
+pub enum FoundFile {
+    File(PathBuf),
+    // with --archives, a file inside a zip or tar archive
+    Member(ArchiveMember),
+}
+
+impl FoundFile {
+    pub fn path(&self) -> &PathBuf {
+        match self {
+            FoundFile::File(path) => path,
+            FoundFile::Member(member) => &member.path,
+        }
+    }
+
+    // the contents when they have been read already
+    pub fn contents(&self) -> Option<&[u8]> {
+        match self {
+            FoundFile::File(_) => None,
+            FoundFile::Member(member) => member.contents.as_deref(),
+        }
+    }
+}
+
+pub struct FindFiles<'caller> {
+    folders: Arc<FolderQueue>,
+    holding_folder: bool,
     cur_dir_entry: Option<fs::ReadDir>,
     cur_depth: usize,
+    cur_ignore: Option<Arc<IgnoreDir>>,
+    cur_path: PathBuf,
+    pending_errors: VecDeque<SfindError>,
+    // the archive whose files are being found
+    cur_archive: Option<ArchiveReader>,
     opt: &'caller CommandOptions,
+    folders_to_prune: Option<&'caller Regex>,
+    files_to_prune: Option<&'caller Regex>,
+    files_to_find: Option<&'caller Regex>,
+}
+
+// The file and folder names to find and to prune, compiled once and shared
+// by all the FindFiles iterators of a search
+#[derive(Debug)]
+pub struct FindPatterns {
     folders_to_prune: Option<Regex>,
     files_to_prune: Option<Regex>,
     files_to_find: Option<Regex>,
 }
 
+impl FindPatterns {
+    pub fn new(opt: &CommandOptions, cfg: &ConfigJson) -> Result<FindPatterns, SfindError> {
+        Ok(FindPatterns {
+            folders_to_prune: FindFiles::match_filenames_regex(&cfg.folders_to_prune, true)?,
+            files_to_prune: FindFiles::match_filenames_regex(&cfg.files_to_prune, true)?,
+            files_to_find: FindFiles::match_filenames_regex(&opt.files, opt.find_iname)?,
+        })
+    }
+}
+
 impl PathToScan {
-    pub fn new(path: PathBuf, depth: usize) -> PathToScan {
-        PathToScan { path, depth }
+    pub fn new(path: PathBuf, depth: usize, parent_ignore: Option<Arc<IgnoreDir>>) -> PathToScan {
+        PathToScan { path, depth, parent_ignore }
+    }
+}
+
+impl FolderQueue {
+    pub fn new(opt: &CommandOptions) -> FolderQueue {
+        let folders = opt
+            .folders
+            .iter()
+            .map(|path| PathToScan::new(path.to_path_buf(), 1, None))
+            .collect();
+
+        FolderQueue {
+            state: Mutex::new(FolderQueueState { folders, busy: 0 }),
+            folder_pushed: Condvar::new(),
+        }
+    }
+
+    fn push(&self, path_to_scan: PathToScan) {
+        let mut state = self.state.lock().unwrap();
+        state.folders.push_back(path_to_scan);
+        self.folder_pushed.notify_one();
+    }
+
+    fn release(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.busy -= 1;
+        self.folder_pushed.notify_all();
+    }
+
+    // wait for the next folder to scan, returns None when all the folders
+    // have been scanned and no iterator can push any more
+    fn pop(&self, was_busy: bool) -> Option<PathToScan> {
+        let mut state = self.state.lock().unwrap();
+        if was_busy {
+            state.busy -= 1;
+        }
+        loop {
+            if let Some(path_to_scan) = state.folders.pop_front() {
+                state.busy += 1;
+                return Some(path_to_scan);
+            }
+            if state.busy == 0 {
+                // wake up the other iterators so that they can finish
+                self.folder_pushed.notify_all();
+                return None;
+            }
+            state = self.folder_pushed.wait(state).unwrap();
+        }
+    }
+}
+
+// a FindFiles that stops early must not leave the other iterators waiting
+impl<'caller> Drop for FindFiles<'caller> {
+    fn drop(&mut self) {
+        if self.holding_folder {
+            self.folders.release();
+        }
     }
 }
 
 macro_rules! continue_on_err {
-    ($report:expr, $val:expr, $msg:literal, $file:expr) => {
+    ($report:expr, $val:expr, $path:expr) => {
         match $val {
             Ok(v) => v,
             Err(e) => {
-                if($report) {
-                    eprintln!($msg, $file, e);
+                if $report {
+                    break Some(Err(SfindError::io($path, e)));
                 }
                 continue;
             }
@@ -56,12 +183,28 @@ macro_rules! continue_on_err {
     };
 }
 
+// The iterator returns the errors found while scanning the folders
+// so that the caller can decide which to report and how.
 impl<'caller> Iterator for FindFiles<'caller> {
-    type Item = PathBuf;
+    type Item = Result<FoundFile, SfindError>;
 
     fn next(&mut self) -> Option<Self::Item> {
         loop {
-            match self.cur_dir_entry()?.next() {
+            if let Some(error) = self.pending_errors.pop_front() {
+                break Some(Err(error));
+            }
+            if let Some(mut archive) = self.cur_archive.take() {
+                let member = archive.next_member(|member, len, modified| self.file_selected(member, len, modified));
+                if let Some(member) = member {
+                    self.cur_archive = Some(archive);
+                    break Some(member.map(FoundFile::Member));
+                }
+            }
+            let Some(cur_dir_entry) = self.cur_dir_entry() else {
+                break self.pending_errors.pop_front().map(Err);
+            };
+
+            match cur_dir_entry.next() {
                 // no more files in this dir
                 None => {
                     // set to None and try again on the next folder
@@ -69,11 +212,11 @@ impl<'caller> Iterator for FindFiles<'caller> {
                     continue;
                 }
                 Some(entry) => {
-                    let entry = continue_on_err!(true, entry, "error read_dir next 2 {} - {}", "");
-                    let m = continue_on_err!(true, entry.metadata(), "error read_dir metadata {} - {}", entry.path().display());
+                    let entry = continue_on_err!(true, entry, &self.cur_path);
+                    let m = continue_on_err!(true, entry.metadata(), &entry.path());
 
                     let is_dir = if m.is_symlink() {
-                        let m = continue_on_err!(self.opt.report_supressed_errors, fs::metadata(entry.path()), "error read_dir os symlink {} - {}", entry.path().display());
+                        let m = continue_on_err!(self.opt.report_supressed_errors, fs::metadata(entry.path()), &entry.path());
                         m.is_dir()
                     } else {
                         m.is_dir()
@@ -83,13 +226,23 @@ impl<'caller> Iterator for FindFiles<'caller> {
                     if is_dir {
                         // only go deeper if allowed.
                         if self.go_deeper() {
-                            self.push_folder(PathToScan::new(entry.path(), self.cur_depth + 1));
+                            self.push_folder(PathToScan::new(entry.path(), self.cur_depth + 1, self.cur_ignore.clone()));
                         }
                         continue;
                     };
 
+                    // an archive is searched like a folder, and is found
+                    // itself like any other file before its members
+                    if self.opt.find_archives {
+                        if let Some(kind) = ArchiveKind::from_path(&entry.path()) {
+                            if self.scan_archive(&entry, &m) {
+                                self.read_archive(entry.path(), kind);
+                            }
+                        }
+                    }
+
                     if self.return_file(&entry, &m) {
-                        break Some(entry.path());
+                        break Some(Ok(FoundFile::File(entry.path())));
                     }
                 }
             }
@@ -104,16 +257,28 @@ impl<'caller> FindFiles<'caller> {
         // set to read_dir() of the next folder to scan
         while self.cur_dir_entry.is_none() {
             // In a function returning `Option`, `?` returns if we get a `None`.
-            let path_to_scan = self.folders.pop_front()?;
+            let path_to_scan = self.folders.pop(self.holding_folder);
+            self.holding_folder = path_to_scan.is_some();
+            let path_to_scan = path_to_scan?;
 
             match fs::read_dir(path_to_scan.path.clone()) {
                 Err(e) => {
-                    eprintln!("Error: read_dir {} - {}", path_to_scan.path.display(), e);
+                    self.pending_errors.push_back(SfindError::io(&path_to_scan.path, e));
                     continue;
                 }
                 Ok(entry) => {
                     self.cur_dir_entry = Some(entry);
                     self.cur_depth = path_to_scan.depth;
+                    if self.opt.find_use_ignore_files {
+                        // the folders given to search also use the rules of the git repo they are in
+                        let parent_ignore = if path_to_scan.depth == 1 {
+                            IgnoreDir::new_for_ancestors(&path_to_scan.path, &mut self.pending_errors)
+                        } else {
+                            path_to_scan.parent_ignore
+                        };
+                        self.cur_ignore = Some(IgnoreDir::new(parent_ignore, &path_to_scan.path, &mut self.pending_errors));
+                    }
+                    self.cur_path = path_to_scan.path;
                     break;
                 }
             }
@@ -125,10 +290,14 @@ impl<'caller> FindFiles<'caller> {
     fn push_folder(&mut self, path_to_scan: PathToScan) {
         if self.exclude_folder(&path_to_scan.path) {
             if self.opt.debug {
-                eprintln!("Debug: exclude folder {:?}", path_to_scan);
+                eprintln!("Debug: exclude folder {:?}", path_to_scan.path);
+            }
+        } else if self.ignore_entry(&path_to_scan.path, true) {
+            if self.opt.debug {
+                eprintln!("Debug: ignore folder {:?}", path_to_scan.path);
             }
         } else {
-            self.folders.push_back(path_to_scan)
+            self.folders.push(path_to_scan)
         }
     }
 
@@ -139,35 +308,77 @@ impl<'caller> FindFiles<'caller> {
             .unwrap_or(true)
     }
 
-    fn return_file(&self, entry: &DirEntry, m: &Metadata) -> bool {
+    fn return_file(&mut self, entry: &DirEntry, m: &Metadata) -> bool {
         // avoid scanning fifo, etc
         if !Self::file_is_regular(m) {
             return false
         }
 
+        if self.ignore_entry(&entry.path(), false) {
+            if self.opt.debug {
+                eprintln!("Debug: ignore file {:?}", entry.path());
+            }
+            return false
+        }
+
+        self.file_selected(&entry.path(), m.len(), m.modified().ok())
+    }
+
+    // does the file match the names, sizes and times to find
+    fn file_selected(&mut self, path: &Path, len: u64, modified: Option<SystemTime>) -> bool {
         if self.files_to_find.is_some() {
-            if !self.include_file(entry) {
+            if !self.include_file(path) {
                 false
             } else {
                 if self.opt.debug {
-                    eprintln!("Debug: include_file {:?}", entry.path());
+                    eprintln!("Debug: include_file {:?}", path);
                 }
-                self.file_time_allowed(m) && self.file_size_allowed(m)
+                self.file_time_allowed(modified) && self.file_size_allowed(len)
             }
         } else {
             // exclude files that are config to be pruned
-            if self.exclude_file(entry) {
+            if self.exclude_file(path) {
                 if self.opt.debug {
-                    eprintln!("Debug: exclude_file {:?}", entry.path());
+                    eprintln!("Debug: exclude_file {:?}", path);
                 }
                 false
             } else {
                 if self.opt.debug {
-                    eprintln!("Debug: file not included or excluded {:?}", entry.path());
+                    eprintln!("Debug: file not included or excluded {:?}", path);
                 }
-                self.file_time_allowed(m) && self.file_size_allowed(m)
+                self.file_time_allowed(modified) && self.file_size_allowed(len)
+            }
+        }
+    }
+
+    // an archive is pruned and ignored like a folder, the names, sizes and
+    // times to find are checked against the files inside it
+    fn scan_archive(&mut self, entry: &DirEntry, m: &Metadata) -> bool {
+        if !Self::file_is_regular(m) || !self.go_deeper() {
+            return false
+        }
+        if self.ignore_entry(&entry.path(), false) {
+            if self.opt.debug {
+                eprintln!("Debug: ignore archive {:?}", entry.path());
+            }
+            return false
+        }
+        if self.exclude_file(&entry.path()) {
+            if self.opt.debug {
+                eprintln!("Debug: exclude archive {:?}", entry.path());
             }
+            return false
+        }
+        true
+    }
+
+    fn read_archive(&mut self, path: PathBuf, kind: ArchiveKind) {
+        if self.opt.debug {
+            eprintln!("Debug: read archive {:?}", path);
         }
+        // only the names are needed when there are no patterns to grep for
+        let contents_limit = self.opt.has_patterns().then(|| self.opt.size_max.unwrap_or(MEMBER_SIZE_LIMIT));
+        self.cur_archive = Some(ArchiveReader::open(path, kind, contents_limit));
     }
 
     cfg_if::cfg_if! {
@@ -184,40 +395,34 @@ impl<'caller> FindFiles<'caller> {
         }
     }
 
-    fn file_size_allowed(&self, m: &Metadata) -> bool {
-        match self.opt.size_min {
-            None => {}
-            Some(size_min) => {
-                    // is the file too small?
-                if m.len() < size_min {
-                    return false
-                }
+    fn file_size_allowed(&self, len: u64) -> bool {
+        if let Some(size_min) = self.opt.size_min {
+            // is the file too small?
+            if len < size_min {
+                return false
             }
         }
-        match self.opt.size_max {
-            None => {}
-            Some(size_max) => {
-                    // is the file too big?
-                if m.len() > size_max {
-                    return false
-                }
+        if let Some(size_max) = self.opt.size_max {
+            // is the file too big?
+            if len > size_max {
+                return false
             }
         }
         true
     }
 
-    fn file_time_allowed(&self, m: &Metadata) -> bool {
+    fn file_time_allowed(&self, modified: Option<SystemTime>) -> bool {
         match self.opt.time_from {
             None => {
                 // no check required
-                return true
+                true
             }
             Some(time_from) => {
                 // check file times
-                match m.modified().ok() {
+                match modified {
                     None => {
                         // skip time check
-                        return true;
+                        true
                     }
 
                     Some(file_mod_secs) => {
@@ -226,14 +431,14 @@ impl<'caller> FindFiles<'caller> {
                             match self.opt.time_till {
                                 Some(time_until) => {
                                     // is the file too new?
-                                    return file_mod_secs < time_until;
+                                    file_mod_secs < time_until
                                 }
                                 None => {
-                                    return true
+                                    true
                                 }
                             }
                         } else {
-                            return false
+                            false
                         }
                     }
                 }
@@ -243,27 +448,30 @@ impl<'caller> FindFiles<'caller> {
 }
 
 impl<'caller> FindFiles<'caller> {
-    pub fn new(opt: &'caller CommandOptions, cfg: &'caller ConfigJson) -> FindFiles<'caller> {
-        let mut finder = FindFiles {
-            folders: VecDeque::new(),
+    pub fn new(opt: &'caller CommandOptions, find_patterns: &'caller FindPatterns) -> FindFiles<'caller> {
+        FindFiles::new_shared(opt, find_patterns, Arc::new(FolderQueue::new(opt)))
+    }
+
+    // use when many threads each iterate over the folders in the same queue
+    pub fn new_shared(opt: &'caller CommandOptions, find_patterns: &'caller FindPatterns, folders: Arc<FolderQueue>) -> FindFiles<'caller> {
+        FindFiles {
+            folders,
+            holding_folder: false,
             cur_dir_entry: None,
             cur_depth: 0,
+            cur_ignore: None,
+            cur_path: PathBuf::new(),
+            pending_errors: VecDeque::new(),
+            cur_archive: None,
             opt,
-            folders_to_prune: FindFiles::match_filenames_regex(&cfg.folders_to_prune, true),
-            files_to_prune: FindFiles::match_filenames_regex(&cfg.files_to_prune, true),
-            files_to_find: FindFiles::match_filenames_regex(&opt.files, opt.find_iname),
-        };
-        for path in &opt.folders {
-            finder
-                .folders
-                .push_back(PathToScan::new(path.to_path_buf(), 1));
+            folders_to_prune: find_patterns.folders_to_prune.as_ref(),
+            files_to_prune: find_patterns.files_to_prune.as_ref(),
+            files_to_find: find_patterns.files_to_find.as_ref(),
         }
-
-        finder
     }
 
-    fn exclude_folder(&self, folder_path: &Path) -> bool {
-        match &self.folders_to_prune {
+    fn exclude_folder(&mut self, folder_path: &Path) -> bool {
+        match self.folders_to_prune {
             Some(regex) => {
                 let folder_name = match folder_path.file_name() {
                     Some(file_name) => file_name,
@@ -283,7 +491,7 @@ impl<'caller> FindFiles<'caller> {
                         exclude
                     }
                     None => {
-                        eprintln!("Error: folder_name is not utf-8");
+                        self.pending_errors.push_back(SfindError::NonUtf8Path(folder_path.to_path_buf()));
                         true
                     }
                 }
@@ -292,38 +500,70 @@ impl<'caller> FindFiles<'caller> {
         }
     }
 
-    fn match_file(&self, match_regex: Option<&Regex>, entry: &fs::DirEntry, match_basename: bool) -> bool {
+    // is the file or folder ignored by the rules of the folder being scanned
+    fn ignore_entry(&self, path: &Path, is_dir: bool) -> bool {
+        match (&self.cur_ignore, path.file_name().and_then(|name| name.to_str())) {
+            (Some(ignore_dir), Some(name)) => ignore_dir.is_ignored(name, is_dir),
+            _ => false,
+        }
+    }
+
+    fn match_file(match_regex: Option<&Regex>, path: &Path, match_basename: bool) -> Result<bool, SfindError> {
         match_regex
             .map(|regex| {
-                if let Ok(path_or_file_name) =
+                if let Some(path_or_file_name) =
                         if match_basename {
-                            entry.file_name().into_string()
+                            path.file_name().unwrap_or_default().to_str()
                         } else {
-                            entry.path().into_os_string().into_string()
+                            path.to_str()
                         }
                     {
                     //eprintln!("QQQ path_or_file_name {}", path_or_file_name);
                     //eprintln!("QQQ regex {}", regex);
-                    regex.is_match(&path_or_file_name)
+                    Ok(regex.is_match(path_or_file_name))
                 } else {
-                    eprintln!("Error: file_name is not utf-8 {}", entry.path().display());
-                    false
+                    Err(SfindError::NonUtf8Path(path.to_path_buf()))
                 }
             })
-            .unwrap_or(false)
+            .unwrap_or(Ok(false))
+    }
+
+    fn include_file(&mut self, path: &Path) -> bool {
+        let include = if self.opt.find_match_basename {
+            Self::match_file(self.files_to_find, path, true)
+        } else {
+            self.include_path(path)
+        };
+        self.false_on_error(include)
+    }
+
+    // with --path the patterns match the whole path or the path inside the folder searched
+    fn include_path(&self, path: &Path) -> Result<bool, SfindError> {
+        let inside_folders = self.opt.folders.iter().filter_map(|folder| path.strip_prefix(folder).ok());
+        for path in std::iter::once(path).chain(inside_folders) {
+            if Self::match_file(self.files_to_find, path, false)? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
     }
 
-    fn include_file(&self, entry: &fs::DirEntry) -> bool {
-        self.match_file(self.files_to_find.as_ref(), entry, self.opt.find_match_basename)
+    fn exclude_file(&mut self, path: &Path) -> bool {
+        let exclude = Self::match_file(self.files_to_prune, path, true);
+        self.false_on_error(exclude)
     }
 
-    fn exclude_file(&self, entry: &fs::DirEntry) -> bool {
-        self.match_file(self.files_to_prune.as_ref(), entry, true)
+    // keep the error to be returned by the iterator
+    fn false_on_error(&mut self, result: Result<bool, SfindError>) -> bool {
+        result.unwrap_or_else(|e| {
+            self.pending_errors.push_back(e);
+            false
+        })
     }
 
-    fn match_filenames_regex(all_patterns: &[String], case_insensitive: bool) -> Option<Regex> {
+    pub fn match_filenames_regex(all_patterns: &[String], case_insensitive: bool) -> Result<Option<Regex>, SfindError> {
         if all_patterns.is_empty() {
-            None
+            Ok(None)
         } else {
             let mut prune_pattern = String::new();
             prune_pattern.push_str("^(");
@@ -331,57 +571,90 @@ impl<'caller> FindFiles<'caller> {
 
             for pattern in all_patterns {
                 prune_pattern.push_str(sep);
-                prune_pattern.push_str(&FindFiles::glob_pattern_to_regex_pattern(pattern));
+                prune_pattern.push_str(&glob_to_regex_pattern(pattern, GlobSyntax::Find));
                 sep = "|";
             }
             prune_pattern.push_str(")$");
-            match RegexBuilder::new(&prune_pattern)
+            RegexBuilder::new(&prune_pattern)
                 .case_insensitive(case_insensitive)
                 .build()
-            {
-                Ok(regex) => Some(regex),
-                Err(e) => {
-                    eprintln!("Error: bad pattern {} - {}", prune_pattern, e);
-                    None
-                }
-            }
-        }
-    }
-
-    fn glob_pattern_to_regex_pattern(glob_pattern: &str) -> String {
-        let mut regex_pattern = String::new();
-
-        for ch in glob_pattern.chars() {
-            match ch {
-                '*' => regex_pattern.push_str(".*"),
-                '?' => regex_pattern.push('.'),
-                '.' | '+' | '(' | ')' | '|' | '\\' | '[' | ']' | '{' | '}' | '^' | '$' | '#' => {
-                    regex_pattern.push('\\');
-                    regex_pattern.push(ch)
-                }
-                _ => regex_pattern.push(ch),
-            };
+                .map(Some)
+                .map_err(|e| SfindError::BadPattern { pattern: prune_pattern, source: e })
         }
-
-        regex_pattern
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::archive::member_path;
+    use crate::test_support::{test_config, test_options, ScratchFolder};
+
+    fn glob_matches(glob: &str, name: &str) -> bool {
+        let glob_patterns = vec![String::from(glob)];
+        let regex = FindFiles::match_filenames_regex(&glob_patterns, false).unwrap().unwrap();
+        regex.is_match(name)
+    }
 
     #[test]
-    fn glob_pattern_to_regex_pattern() {
-        assert_eq!(FindFiles::glob_pattern_to_regex_pattern("fixed"), "fixed");
-        assert_eq!(
-            FindFiles::glob_pattern_to_regex_pattern("file.type"),
-            "file\\.type"
-        );
-        assert_eq!(
-            FindFiles::glob_pattern_to_regex_pattern("*.type"),
-            ".*\\.type"
-        );
+    fn glob_character_sets() {
+        assert!(glob_matches("test_[0-9]*.py", "test_1_parse.py"));
+        assert!(!glob_matches("test_[0-9]*.py", "test_a.py"));
+        assert!(glob_matches("[abc].txt", "b.txt"));
+        assert!(!glob_matches("[abc].txt", "d.txt"));
+        assert!(glob_matches("[!a-z]*", "Makefile"));
+        assert!(glob_matches("[^a-z]*", "_build"));
+        assert!(!glob_matches("[!a-z]*", "makefile"));
+        // a ] first in the set is one of the characters
+        assert!(glob_matches("[]x]", "]"));
+        assert!(glob_matches("[!]]", "x"));
+        assert!(!glob_matches("[!]]", "]"));
+        assert!(glob_matches("x[-.]y", "x.y"));
+        assert!(glob_matches("[\\d]", "d"));
+        assert!(!glob_matches("[\\d]", "1"));
+        // no closing ] matches [ itself
+        assert!(glob_matches("a[b", "a[b"));
+    }
+
+    #[test]
+    fn glob_braces() {
+        assert!(glob_matches("*.{rs,toml}", "Cargo.toml"));
+        assert!(glob_matches("*.{rs,toml}", "main.rs"));
+        assert!(!glob_matches("*.{rs,toml}", "main.rs.orig"));
+        assert!(glob_matches("{a,b{c,d}}.txt", "bd.txt"));
+        assert!(!glob_matches("{a,b{c,d}}.txt", "b.txt"));
+        assert!(glob_matches("{,x}y", "y"));
+        // no closing } and a , outside {} match themselves
+        assert!(glob_matches("a{b,c", "a{b,c"));
+        assert!(glob_matches("a,b}", "a,b}"));
+    }
+
+    #[test]
+    fn glob_escapes() {
+        assert!(glob_matches("\\*.txt", "*.txt"));
+        assert!(!glob_matches("\\*.txt", "a.txt"));
+        assert!(glob_matches("what\\?", "what?"));
+        assert!(glob_matches("\\[x\\]", "[x]"));
+        assert!(glob_matches("\\{a,b\\}", "{a,b}"));
+        assert!(glob_matches("a\\\\b", "a\\b"));
+        assert!(glob_matches("end\\", "end\\"));
+        assert!(glob_matches("(a|b)+#", "(a|b)+#"));
+    }
+
+    #[test]
+    fn glob_paths() {
+        assert!(glob_matches("src/**/mod.rs", "src/find/mod.rs"));
+        assert!(glob_matches("src/**/mod.rs", "src/a/b/c/mod.rs"));
+        assert!(glob_matches("src/**/mod.rs", "src/mod.rs"));
+        assert!(!glob_matches("src/**/mod.rs", "src/a/xmod.rs"));
+        assert!(glob_matches("**/*.class", "./lib/foo.jar!/com/x/Y.class"));
+        assert!(glob_matches("**", "a/b/c"));
+        assert!(glob_matches("src/**", "src/a/b.rs"));
+        // * and ? match the / between folders as well
+        assert!(glob_matches("*x.txt", "./src/a/x.txt"));
+        assert!(glob_matches("src/*.rs", "src/a/lib.rs"));
+        assert!(glob_matches("src?lib.rs", "src/lib.rs"));
+        assert!(glob_matches("x**/y", "x/z/y"));
     }
 
     #[test]
@@ -406,14 +679,14 @@ mod tests {
     #[test]
     fn regex_vec_match() {
         let glob_patterns = vec![String::from("*.txt")];
-        let regex = FindFiles::match_filenames_regex(&glob_patterns, false).unwrap();
+        let regex = FindFiles::match_filenames_regex(&glob_patterns, false).unwrap().unwrap();
         assert_eq!(regex.as_str(), r#"^(.*\.txt)$"#);
 
         let haystack = String::from("abc.txt");
         assert!(regex.is_match(&haystack));
 
         let glob_patterns = vec![String::from("*.txt"), String::from("*.rs")];
-        let regex = FindFiles::match_filenames_regex(&glob_patterns, false).unwrap();
+        let regex = FindFiles::match_filenames_regex(&glob_patterns, false).unwrap().unwrap();
         assert_eq!(regex.as_str(), r#"^(.*\.txt|.*\.rs)$"#);
 
         assert!(regex.is_match(&haystack));
@@ -424,4 +697,43 @@ mod tests {
         let haystack = String::from("abc.toml");
         assert!(!regex.is_match(&haystack));
     }
+
+    // the paths found in top with the args before the folder
+    fn find(top: &Path, args: &[&str]) -> Vec<PathBuf> {
+        let top = top.display().to_string();
+        let args: Vec<&str> = std::iter::once("--no-ignore").chain(args.iter().copied()).chain([top.as_str()]).collect();
+        let opt = test_options(&args);
+        let find_patterns = FindPatterns::new(&opt, &test_config()).unwrap();
+        let mut paths: Vec<PathBuf> = FindFiles::new(&opt, &find_patterns).map(|found| found.unwrap().path().clone()).collect();
+        paths.sort();
+        paths
+    }
+
+    #[test]
+    fn archives_are_found() {
+        let top = ScratchFolder::new("archives");
+        let mut zip = zip::ZipWriter::new(fs::File::create(top.join("lib.jar")).unwrap());
+        zip.start_file("a.txt", zip::write::SimpleFileOptions::default()).unwrap();
+        zip.finish().unwrap();
+
+        let jar = top.join("lib.jar");
+        let member = member_path(&jar, "a.txt");
+        assert_eq!(find(top.path(), &["--archives", "*.jar"]), [jar.as_path()]);
+        assert_eq!(find(top.path(), &["--archives", "*.txt"]), [member.as_path()]);
+        assert_eq!(find(top.path(), &["--archives"]), [jar.as_path(), member.as_path()]);
+        assert_eq!(find(top.path(), &[]), [jar.as_path()]);
+    }
+
+    #[test]
+    fn path_patterns() {
+        let top = ScratchFolder::new("paths");
+        let file = top.write("src/a/x.txt", "");
+
+        // the whole path matches, as does the path inside the folder searched
+        assert_eq!(find(top.path(), &["-p", "*x.txt"]), [file.as_path()]);
+        assert_eq!(find(top.path(), &["-p", "src/**/x.txt"]), [file.as_path()]);
+        assert!(find(top.path(), &["-p", "a/*.txt"]).is_empty());
+        let path = format!("{}/**/x.txt", top.path().display());
+        assert_eq!(find(top.path(), &["-p", &path]), [file.as_path()]);
+    }
 }
@@ -0,0 +1,163 @@
+// The rules that a glob is translated to a regex with
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GlobSyntax {
+    // the names and paths to find and prune, {a,b} matches either. For
+    // compatibility with earlier versions * ? and [!a] match the / between
+    // folders with --path as well, so that -p '*x.txt' still finds src/a/x.txt
+    Find,
+    // .gitignore patterns, only a ** folder matches across the / between folders
+    Ignore,
+}
+
+// Glob syntax: * and ? match any characters, [abc] and [!a-z] match one of a set
+// and \ makes the next character match itself. A ** folder matches any number
+// of folders including none.
+// A [ or { without its closing bracket matches itself.
+pub fn glob_to_regex_pattern(glob: &str, syntax: GlobSyntax) -> String {
+    let chars: Vec<char> = glob.chars().collect();
+    let mut regex_pattern = String::new();
+    // the number of {} that are open
+    let mut open_braces = 0;
+    let mut index = 0;
+
+    while index < chars.len() {
+        let ch = chars[index];
+        index += 1;
+        match ch {
+            '\\' => {
+                let escaped = chars.get(index).copied().unwrap_or('\\');
+                index += 1;
+                regex_pattern.push_str(&regex::escape(&escaped.to_string()));
+            }
+            '*' => {
+                let mut stars = 1;
+                while chars.get(index) == Some(&'*') {
+                    stars += 1;
+                    index += 1;
+                }
+                // ** is only special as the whole of a folder name
+                let folder_stars = stars > 1 && (index == stars || chars[index - stars - 1] == '/');
+                if folder_stars && chars.get(index) == Some(&'/') {
+                    index += 1;
+                    regex_pattern.push_str("(?:.*/)?");
+                } else if syntax == GlobSyntax::Find || (folder_stars && index == chars.len()) {
+                    regex_pattern.push_str(".*");
+                } else {
+                    regex_pattern.push_str("[^/]*");
+                }
+            }
+            '?' => regex_pattern.push_str(if syntax == GlobSyntax::Find { "." } else { "[^/]" }),
+            '[' => match glob_class_to_regex(&chars[index..], syntax) {
+                Some((class, len)) => {
+                    regex_pattern.push_str(&class);
+                    index += len;
+                }
+                None => regex_pattern.push_str("\\["),
+            },
+            '{' if syntax == GlobSyntax::Find && has_closing_brace(&chars[index..]) => {
+                open_braces += 1;
+                regex_pattern.push_str("(?:");
+            }
+            ',' if open_braces > 0 => regex_pattern.push('|'),
+            '}' if open_braces > 0 => {
+                open_braces -= 1;
+                regex_pattern.push(')');
+            }
+            _ => regex_pattern.push_str(&regex::escape(&ch.to_string())),
+        };
+    }
+
+    regex_pattern
+}
+
+// The regex for the [] set that chars start inside of and the number of chars
+// up to and including the ], None when there is no ]
+fn glob_class_to_regex(chars: &[char], syntax: GlobSyntax) -> Option<(String, usize)> {
+    let mut class = String::from("[");
+    let mut index = 0;
+    if matches!(chars.first(), Some('!') | Some('^')) {
+        class.push('^');
+        // a set that matches anything else still does not match the / between folders
+        if syntax == GlobSyntax::Ignore {
+            class.push('/');
+        }
+        index += 1;
+    }
+    let first = index;
+    loop {
+        let ch = *chars.get(index)?;
+        index += 1;
+        match ch {
+            // a ] first in the set is one of the characters
+            ']' if index - 1 > first => break,
+            '\\' => {
+                let escaped = *chars.get(index)?;
+                index += 1;
+                // \d and the like have a meaning of their own in a regex
+                if !escaped.is_ascii_alphanumeric() {
+                    class.push('\\');
+                }
+                class.push(escaped);
+            }
+            '[' | ']' | '&' | '~' | '^' => {
+                class.push('\\');
+                class.push(ch);
+            }
+            _ => class.push(ch),
+        }
+    }
+    class.push(']');
+    Some((class, index))
+}
+
+// is there a } for the { before chars, counting the {} inside
+fn has_closing_brace(chars: &[char]) -> bool {
+    let mut depth = 0;
+    let mut index = 0;
+    while index < chars.len() {
+        match chars[index] {
+            '\\' => index += 1,
+            '{' => depth += 1,
+            '}' if depth == 0 => return true,
+            '}' => depth -= 1,
+            _ => {}
+        }
+        index += 1;
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn find_syntax() {
+        let find = |glob| glob_to_regex_pattern(glob, GlobSyntax::Find);
+        assert_eq!(find("fixed"), "fixed");
+        assert_eq!(find("file.type"), "file\\.type");
+        assert_eq!(find("*.type"), ".*\\.type");
+        assert_eq!(find("*.{rs,toml}"), ".*\\.(?:rs|toml)");
+        assert_eq!(find("test_[0-9]*.py"), "test_[0-9].*\\.py");
+        assert_eq!(find("[!a-z]?"), "[^a-z].");
+        assert_eq!(find("*/?"), ".*/.");
+        assert_eq!(find("src/**/mod.rs"), "src/(?:.*/)?mod\\.rs");
+    }
+
+    #[test]
+    fn ignore_syntax() {
+        let ignore = |glob| glob_to_regex_pattern(glob, GlobSyntax::Ignore);
+        assert_eq!(ignore("file.type"), "file\\.type");
+        assert_eq!(ignore("*.o"), "[^/]*\\.o");
+        assert_eq!(ignore("a?c"), "a[^/]c");
+        assert_eq!(ignore("[!a-z]x"), "[^/a-z]x");
+        assert_eq!(ignore("**/build"), "(?:.*/)?build");
+        assert_eq!(ignore("a/**/b"), "a/(?:.*/)?b");
+        assert_eq!(ignore("out/**"), "out/.*");
+        assert_eq!(ignore("x**/y"), "x[^/]*/y");
+        assert_eq!(ignore("\\#notes"), "\\#notes");
+        assert_eq!(ignore("[abc"), "\\[abc");
+        // {} are only special when finding
+        assert_eq!(ignore("{a,b}"), "\\{a,b\\}");
+    }
+}
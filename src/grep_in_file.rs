@@ -1,49 +1,151 @@
+use std::borrow::Cow;
 use std::collections::VecDeque;
+use std::ops::Range;
 use std::path::PathBuf;
 
-use std::io::{BufRead, BufReader, Read, Seek};
+use std::io::{self, Read, Write};
 use std::{fs, iter, mem};
 
-use anyhow::{anyhow, Result};
-use regex::{Regex, RegexBuilder};
+use regex::{Regex, RegexSet};
 
-use encoding_rs::{UTF_8, WINDOWS_1252};
+use encoding_rs::{Encoding, UTF_8, WINDOWS_1252};
 
-pub use crate::command_options::CommandOptions;
+pub use crate::command_options::{BinaryMode, CommandOptions, GrepReport, OutputFormat};
+use crate::colours::Colours;
+use crate::decompress::{Compression, MAGIC_LEN};
+use crate::diff_output::{self, LineChange};
+use crate::error::SfindError;
+use crate::file_encoding::{self, FileLines, TextOffsets};
+use crate::json_output::{JsonEvent, JsonSubmatch};
+use crate::write_file;
+
+type Result<T> = std::result::Result<T, SfindError>;
 
 pub struct GrepPatterns {
     pub patterns: Vec<Regex>,
+    // the fixed strings and regexes the patterns were made from
+    pub pattern_texts: Vec<String>,
+    // finds which of the patterns match in one pass
+    pattern_set: Option<RegexSet>,
+    // a line that matches any of these is not selected
+    pub negative_patterns: Vec<Regex>,
+    pub invert_match: bool,
+    // a file is only reported when every pattern matches somewhere in it
+    pub all_of: bool,
+    // a file is not reported when any of these match anywhere in it
+    pub none_of_patterns: Vec<Regex>,
+    // the --replace template
+    pub replacement: Option<String>,
+    // with -o print only this capture group of each match
+    pub only_group: Option<usize>,
 }
 
-#[derive(Debug)]
+// what was found while reading the lines of a file
+#[derive(Debug, Default)]
+struct LinesFound {
+    matched_lines: usize,
+    matches: usize,
+    // which of the patterns have matched, for --all-of
+    patterns_seen: Vec<bool>,
+    // a --none-of pattern has matched
+    disqualified: bool,
+}
+
+#[derive(Debug, Clone)]
 pub struct GrepMatch {
-    pattern_index: usize,
-    start: usize,
-    end: usize,
+    pub pattern_index: usize,
+    pub start: usize,
+    pub end: usize,
+}
+
+// The lines of a whole file, for --multiline
+struct TextLines<'a> {
+    text: &'a str,
+    // byte offset of the start of each line
+    starts: Vec<usize>,
+}
+
+// Matches that share lines, with their offsets into the whole file
+#[derive(Debug)]
+struct MatchSpan {
+    first_line: usize,
+    last_line: usize,
+    matches: Vec<GrepMatch>,
 }
 
 pub struct GrepInFile<'caller> {
     opt: &'caller CommandOptions,
     patterns: &'caller GrepPatterns,
+    colours: &'caller Colours,
     file_path: &'caller PathBuf,
+    display_path: String,
     num_before: usize,
-    before_lines: VecDeque<String>,
+    // the offset in the file and text of the lines that may be printed before a match
+    before_lines: VecDeque<(u64, String)>,
     line_number: usize,
     num_after: usize,
+    // the number of the last line printed, 0 before the first
+    last_printed_line: usize,
+    // how the text being searched was decoded, to find offsets in the file
+    encoding: &'static Encoding,
+    // the contents of a file in an archive, read instead of file_path
+    contents: Option<&'caller [u8]>,
 }
 
-fn fixed_to_regex(fixed: &str, case_insensitive: bool) -> Result<Regex> {
-    RegexBuilder::new(&GrepPatterns::quote_regex(fixed))
-        .case_insensitive(case_insensitive)
-        .build()
-        .map_err(|_| anyhow!("failed to compile regex for {}", fixed))
+fn fixed_to_regex(fixed: &str, opt: &CommandOptions) -> Result<Regex> {
+    build_regex(&GrepPatterns::quote_regex(fixed), has_upper_case(fixed, false), fixed, opt)
 }
 
-fn pattern_to_regex(pattern: &str, case_insensitive: bool) -> Result<Regex> {
-    RegexBuilder::new(pattern)
-        .case_insensitive(case_insensitive)
-        .build()
-        .map_err(|_| anyhow!("failed to compile regex for {}", pattern))
+fn pattern_to_regex(pattern: &str, opt: &CommandOptions) -> Result<Regex> {
+    build_regex(pattern, has_upper_case(pattern, true), pattern, opt)
+}
+
+// does the text have upper case letters, ignoring escapes like \S in a regex
+fn has_upper_case(text: &str, is_regex: bool) -> bool {
+    let mut chars = text.chars();
+    while let Some(ch) = chars.next() {
+        if is_regex && ch == '\\' {
+            chars.next();
+        } else if ch.is_uppercase() {
+            return true;
+        }
+    }
+    false
+}
+
+fn build_regex(regex_pattern: &str, has_upper_case: bool, pattern: &str, opt: &CommandOptions) -> Result<Regex> {
+    let mut regex_pattern = regex_pattern.to_string();
+    if opt.grep_word_regexp {
+        // as grep -w, the characters either side are not word characters, even
+        // when the pattern starts or ends with one that is not
+        regex_pattern = format!(r"\b{{start-half}}(?:{})\b{{end-half}}", regex_pattern);
+    }
+    if opt.grep_line_regexp {
+        regex_pattern = format!("^(?:{})$", regex_pattern);
+    }
+    // --smart-case only makes a match case sensitive
+    let case_insensitive = opt.grep_ignore_case && !(opt.grep_smart_case && has_upper_case);
+
+    // the flags go in the pattern so that the pattern set is built with them
+    let flags: String = [(case_insensitive, 'i'), (opt.grep_multiline, 'm')]
+        .iter()
+        .filter_map(|&(on, flag)| on.then_some(flag))
+        .collect();
+    if !flags.is_empty() {
+        regex_pattern = format!("(?{}){}", flags, regex_pattern);
+    }
+
+    Regex::new(&regex_pattern).map_err(|e| SfindError::BadPattern { pattern: pattern.to_string(), source: e })
+}
+
+// All the patterns in one automaton, so that a line is only searched once to
+// find which patterns match. None when there are too few patterns to gain from
+// it or the set is too big to build.
+fn pattern_set(patterns: &[Regex]) -> Option<RegexSet> {
+    if patterns.len() < 2 {
+        return None;
+    }
+    RegexSet::new(patterns.iter().map(|regex| regex.as_str())).ok()
 }
 
 impl GrepPatterns {
@@ -51,33 +153,171 @@ impl GrepPatterns {
         let fixed = opt
             .fixed_strings
             .iter()
-            .map(|fixed| fixed_to_regex(fixed, opt.grep_ignore_case));
+            .map(|fixed| fixed_to_regex(fixed, opt));
         let regex = opt
             .regex_patterns
             .iter()
-            .map(|pattern| pattern_to_regex(pattern, opt.grep_ignore_case));
+            .map(|pattern| pattern_to_regex(pattern, opt));
 
         let patterns: Result<Vec<_>> = fixed.chain(regex).collect();
         let patterns = patterns?;
+        let pattern_texts = opt.fixed_strings.iter().chain(&opt.regex_patterns).cloned().collect();
+
+        let not_fixed = opt
+            .not_fixed_strings
+            .iter()
+            .map(|fixed| fixed_to_regex(fixed, opt));
+        let not_regex = opt
+            .not_regex_patterns
+            .iter()
+            .map(|pattern| pattern_to_regex(pattern, opt));
+
+        let negative_patterns: Result<Vec<_>> = not_fixed.chain(not_regex).collect();
+        let negative_patterns = negative_patterns?;
 
-        Ok(GrepPatterns { patterns })
+        let none_of_patterns: Result<Vec<_>> = opt
+            .none_of_patterns
+            .iter()
+            .map(|pattern| pattern_to_regex(pattern, opt))
+            .collect();
+        let none_of_patterns = none_of_patterns?;
+
+        Ok(GrepPatterns {
+            pattern_set: pattern_set(&patterns),
+            patterns,
+            pattern_texts,
+            negative_patterns,
+            invert_match: opt.grep_invert_match,
+            all_of: opt.grep_all_of,
+            none_of_patterns,
+            replacement: opt.grep_replacement.clone(),
+            only_group: opt.grep_only_group,
+        })
     }
 
-    pub fn find_match(&self, line: &str) -> Vec<GrepMatch> {
-        let mut matches: Vec<_> = self
-            .patterns
+    // true when the lines of a file cannot be reported until all of it is read
+    pub fn has_file_conditions(&self) -> bool {
+        self.all_of || !self.none_of_patterns.is_empty()
+    }
+
+    fn file_qualifies(&self, found: &LinesFound) -> bool {
+        !found.disqualified && (!self.all_of || found.patterns_seen.iter().all(|&seen| seen))
+    }
+
+    // true when reading more of the file cannot change whether it qualifies
+    fn file_decided(&self, found: &LinesFound) -> bool {
+        self.none_of_patterns.is_empty() && self.file_qualifies(found)
+    }
+
+    // A line is selected when it matches any of the patterns, or there are only
+    // negative patterns, and matches none of the negative patterns.
+    // Returns the matches to show for a selected line, None if it is not selected.
+    pub fn select_line(&self, line: &str) -> Option<Vec<GrepMatch>> {
+        let matches = self.find_match(line);
+        let selected = (self.patterns.is_empty() || !matches.is_empty())
+            && !self.negative_patterns.iter().any(|regex| regex.is_match(line));
+
+        match (selected, self.invert_match) {
+            (true, false) => Some(matches),
+            // nothing in an inverted line is highlighted
+            (false, true) => Some(vec![]),
+            _ => None,
+        }
+    }
+
+    // Find the matches in the whole text and group them by the lines they cover.
+    // A span is dropped when any of its lines matches a negative pattern.
+    fn find_spans(&self, lines: &TextLines) -> Vec<MatchSpan> {
+        let mut spans: Vec<MatchSpan> = vec![];
+        for m in self.find_match(lines.text) {
+            let first_line = lines.line_index(m.start);
+            let last_line = lines.line_index(if m.end > m.start { m.end - 1 } else { m.start });
+            match spans.last_mut() {
+                Some(span) if first_line <= span.last_line => {
+                    span.last_line = span.last_line.max(last_line);
+                    span.matches.push(m);
+                }
+                _ => spans.push(MatchSpan { first_line, last_line, matches: vec![m] }),
+            }
+        }
+
+        spans.retain(|span| {
+            !(span.first_line..=span.last_line).any(|line_index| {
+                let (_, line) = lines.line(line_index);
+                self.negative_patterns.iter().any(|regex| regex.is_match(line))
+            })
+        });
+        spans
+    }
+
+    // Replace each match with the expanded --replace template.
+    // Returns the new text and where the replacements are in it.
+    pub fn replace_matches(&self, text: &str, matches: &[GrepMatch]) -> (String, Vec<GrepMatch>) {
+        let template = self.replacement.as_deref().unwrap_or("");
+        let mut replaced = String::with_capacity(text.len());
+        let mut replacements = vec![];
+        let mut last_end = 0;
+
+        for m in matches {
+            if m.start < last_end {
+                // overlaps a match that has been replaced
+                continue;
+            }
+            replaced.push_str(&text[last_end..m.start]);
+            let start = replaced.len();
+            // find the match again for its capture groups
+            if let Some(captures) = self.patterns[m.pattern_index].captures_at(text, m.start) {
+                captures.expand(template, &mut replaced);
+            }
+            replacements.push(GrepMatch { pattern_index: m.pattern_index, start, end: replaced.len() });
+            last_end = m.end;
+        }
+        replaced.push_str(&text[last_end..]);
+
+        (replaced, replacements)
+    }
+
+    // the index of each pattern that matched, in order
+    pub fn matched_patterns(&self, matches: &[GrepMatch]) -> Vec<usize> {
+        let mut indexes: Vec<_> = matches.iter().map(|m| m.pattern_index).collect();
+        indexes.sort();
+        indexes.dedup();
+        indexes
+    }
+
+    // -o: the parts of the line to print, the capture group of each match with --only-group
+    pub fn only_matching(&self, line: &str, matches: &[GrepMatch]) -> Vec<GrepMatch> {
+        let mut parts: Vec<_> = matches
             .iter()
-            .enumerate()
-            .flat_map(|(pattern_index, regex)| {
-                regex.find_iter(line).map(move |m| GrepMatch {
-                    pattern_index,
-                    start: m.start(),
-                    end: m.end(),
-                })
+            .filter_map(|m| match self.only_group {
+                None => Some(m.clone()),
+                Some(group) => self.patterns[m.pattern_index]
+                    .captures_at(line, m.start)
+                    .and_then(|captures| captures.get(group))
+                    .map(|group| GrepMatch { pattern_index: m.pattern_index, start: group.start(), end: group.end() }),
             })
+            .filter(|part| part.end > part.start)
             .collect();
 
-        matches.sort_by(|a: &GrepMatch, b: &GrepMatch| a.start.cmp(&b.start));
+        parts.sort_by_key(|part| part.start);
+        without_overlaps(&parts)
+    }
+
+    pub fn find_match(&self, line: &str) -> Vec<GrepMatch> {
+        let find_iter = |pattern_index: usize| {
+            self.patterns[pattern_index].find_iter(line).map(move |m| GrepMatch {
+                pattern_index,
+                start: m.start(),
+                end: m.end(),
+            })
+        };
+        let mut matches: Vec<_> = match &self.pattern_set {
+            // only look for the positions of the patterns that are in the line
+            Some(pattern_set) => pattern_set.matches(line).into_iter().flat_map(find_iter).collect(),
+            None => (0..self.patterns.len()).flat_map(find_iter).collect(),
+        };
+
+        matches.sort_by_key(|m| m.start);
         matches
     }
 
@@ -99,24 +339,170 @@ impl GrepPatterns {
     }
 }
 
-fn colour_match_line(line: &str, matches: &[GrepMatch]) -> String {
-    let colour_codes_len = GrepInFile::COLOUR_MATCH[0].len() + GrepInFile::COLOUR_END.len();
-    let mut coloured_line = String::with_capacity(line.len() + colour_codes_len * matches.len());
-    let mut last_end = 0;
+impl<'a> TextLines<'a> {
+    fn new(text: &'a str) -> TextLines<'a> {
+        let starts = iter::once(0)
+            .chain(text.match_indices('\n').map(|(index, _)| index + 1))
+            .filter(|&start| start == 0 || start < text.len())
+            .collect();
+        TextLines { text, starts }
+    }
+
+    fn len(&self) -> usize {
+        self.starts.len()
+    }
+
+    fn line_index(&self, offset: usize) -> usize {
+        self.starts.partition_point(|&start| start <= offset) - 1
+    }
+
+    // the text of the lines first..=last including the newline at the end
+    fn span_text(&self, first: usize, last: usize) -> (usize, &'a str) {
+        let start = self.starts[first];
+        let end = self.starts.get(last + 1).copied().unwrap_or(self.text.len());
+        (start, &self.text[start..end])
+    }
+
+    // the offset of the start of the line and its text without the newline
+    fn line(&self, index: usize) -> (usize, &'a str) {
+        let start = self.starts[index];
+        let end = self.starts.get(index + 1).copied().unwrap_or(self.text.len());
+        let line = &self.text[start..end];
+        (start, line.strip_suffix('\n').unwrap_or(line))
+    }
+}
 
+impl MatchSpan {
+    // the matches with their offsets from the start of the span's first line
+    fn shifted_matches(&self, span_start: usize) -> Vec<GrepMatch> {
+        self.matches
+            .iter()
+            .map(|m| GrepMatch { pattern_index: m.pattern_index, start: m.start - span_start, end: m.end - span_start })
+            .collect()
+    }
+}
+
+// the part of each match that is in the line, relative to the start of the line
+fn matches_in_line(matches: &[GrepMatch], line_start: usize, line_len: usize) -> Vec<GrepMatch> {
+    let line_end = line_start + line_len;
+    matches
+        .iter()
+        // a match that ends with the newline of the line before does not show
+        .filter(|m| m.start <= line_end && (m.end > line_start || m.start >= line_start))
+        .map(|m| GrepMatch {
+            pattern_index: m.pattern_index,
+            start: m.start.max(line_start) - line_start,
+            end: m.end.min(line_end) - line_start,
+        })
+        .collect()
+}
+
+// 1 based column of the first match in characters, 1 when nothing in the line is highlighted
+fn match_column(line: &str, matches: &[GrepMatch]) -> usize {
+    matches.first().map_or(0, |m| line[..m.start].chars().count()) + 1
+}
+
+// bytes in each row of a --binary=hex dump
+const HEX_ROW_LEN: usize = 16;
+
+// The matches as offsets into the bytes that text was decoded from with one
+// character for each byte, sorted by start
+fn byte_matches(text: &str, matches: &[GrepMatch]) -> Vec<GrepMatch> {
+    let mut matches = matches.to_vec();
+    matches.sort_by_key(|m| m.start);
+    let mut index = 0;
+    let mut offset = 0;
+    matches
+        .iter()
+        .map(|m| {
+            offset += text[index..m.start].chars().count();
+            index = m.start;
+            let len = text[m.start..m.end].chars().count();
+            GrepMatch { pattern_index: m.pattern_index, start: offset, end: offset + len }
+        })
+        .collect()
+}
+
+// The bytes to dump: the rows of each match with a row either side, merged when they touch
+fn hex_dump_ranges(bytes_len: usize, matches: &[GrepMatch]) -> Vec<Range<usize>> {
+    let mut ranges: Vec<Range<usize>> = vec![];
     for m in matches {
-        // deal with overlap
-        let m_start = if m.start < last_end {
-            last_end
-        } else {
-            m.start
+        let start = (m.start / HEX_ROW_LEN).saturating_sub(1) * HEX_ROW_LEN;
+        let end = ((m.end.max(m.start + 1) - 1) / HEX_ROW_LEN + 2) * HEX_ROW_LEN;
+        let range = start..end.min(bytes_len);
+        match ranges.last_mut() {
+            Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+            _ => ranges.push(range),
+        }
+    }
+    ranges
+}
+
+// one row of a hex dump, the hex of each byte then the printable characters,
+// with the bytes of the matches coloured
+fn hex_row(row: &[u8], row_start: usize, matches: &[GrepMatch], colours: &Colours) -> String {
+    let mut hex = String::new();
+    let mut chars = String::new();
+    for index in 0..HEX_ROW_LEN {
+        if index == HEX_ROW_LEN / 2 {
+            hex.push(' ');
+        }
+        let byte = match row.get(index) {
+            Some(&byte) => byte,
+            None => {
+                // line up the characters of a short last row
+                hex.push_str("   ");
+                continue;
+            }
+        };
+        let offset = row_start + index;
+        let colour = matches
+            .iter()
+            .find(|m| m.start <= offset && offset < m.end)
+            .and_then(|m| colours.matches.get(m.pattern_index % colours.matches.len().max(1)));
+        let paint = |text: &str| match colour {
+            Some(colour) => colours.paint(colour, text),
+            None => text.to_string(),
         };
-        let colour_index = m.pattern_index % GrepInFile::COLOUR_MATCH.len();
+        hex.push_str(&paint(&format!("{:02x}", byte)));
+        hex.push(' ');
+        let ch = if byte.is_ascii_graphic() || byte == b' ' { byte as char } else { '.' };
+        chars.push_str(&paint(&ch.to_string()));
+    }
+    format!("{} |{}|", hex, chars)
+}
+
+// The matches sorted by start with the part of a match that overlaps the match
+// before it removed. A match inside the one before is dropped.
+fn without_overlaps(matches: &[GrepMatch]) -> Vec<GrepMatch> {
+    let mut last_end = 0;
+    matches
+        .iter()
+        .filter_map(|m| {
+            if m.end < last_end || (m.end == last_end && m.start < last_end) {
+                return None;
+            }
+            let start = m.start.max(last_end);
+            last_end = m.end;
+            Some(GrepMatch { pattern_index: m.pattern_index, start, end: m.end })
+        })
+        .collect()
+}
 
-        coloured_line.push_str(&line[last_end..m_start]);
-        coloured_line.push_str(GrepInFile::COLOUR_MATCH[colour_index]);
-        coloured_line.push_str(&line[m_start..m.end]);
-        coloured_line.push_str(GrepInFile::COLOUR_END);
+fn colour_match_line(line: &str, matches: &[GrepMatch], colours: &Colours) -> String {
+    if colours.matches.is_empty() {
+        return line.to_string();
+    }
+
+    let colour_codes_len = colours.matches[0].len() + colours.end.len();
+    let mut coloured_line = String::with_capacity(line.len() + colour_codes_len * matches.len());
+    let mut last_end = 0;
+
+    for m in without_overlaps(matches) {
+        let colour_index = m.pattern_index % colours.matches.len();
+
+        coloured_line.push_str(&line[last_end..m.start]);
+        coloured_line.push_str(&colours.paint(&colours.matches[colour_index], &line[m.start..m.end]));
         last_end = m.end;
     }
     coloured_line.push_str(&line[last_end..]);
@@ -129,138 +515,924 @@ impl<'caller> GrepInFile<'caller> {
         opt: &'caller CommandOptions,
         file_path: &'caller PathBuf,
         patterns: &'caller GrepPatterns,
+        colours: &'caller Colours,
     ) -> GrepInFile<'caller> {
         GrepInFile {
             opt,
             patterns,
+            colours,
             file_path,
+            display_path: file_path.display().to_string(),
             num_before: opt.grep_lines_before.unwrap_or(0),
             before_lines: VecDeque::new(),
             line_number: 0,
             num_after: opt.grep_lines_after.unwrap_or(0),
+            last_printed_line: 0,
+            encoding: UTF_8,
+            contents: None,
         }
     }
 
-    const COLOUR_FILE: &'static str = "\x1b[35m"; // purple
-    const COLOUR_LINE: &'static str = "\x1b[32m"; // green
-    const COLOUR_MATCH: &'static [&'static str] = &[
-        "\x1b[1;31m", // light red
-        "\x1b[33m",   // yellow
-        "\x1b[1;34m", // light blue
-        "\x1b[32m",   // green
-        "\x1b[35m",   // purple
-    ];
-    const COLOUR_END: &'static str = "\x1b[m"; // no colour
+    // search contents that have already been read, for a file inside an archive
+    pub fn with_contents(mut self, contents: &'caller [u8]) -> GrepInFile<'caller> {
+        self.contents = Some(contents);
+        self
+    }
 
-    pub fn search(&mut self) -> Result<()> {
-        let mut file = fs::File::open(self.file_path)?;
-        let mut bin_check_buf = [0u8; 1024];
-        let check_len = file.read(&mut bin_check_buf[..])?;
+    // write the matching lines to out and return the number of lines that matched
+    pub fn search<W: Write>(&mut self, out: &mut W) -> Result<usize> {
+        let found = if self.patterns.has_file_conditions() {
+            // hold on to the lines until it is known that the file qualifies
+            let mut buffer = vec![];
+            match self.search_text(&mut buffer)? {
+                Some(found) if self.patterns.file_qualifies(&found) => {
+                    out.write_all(&buffer).map_err(SfindError::Output)?;
+                    found
+                }
+                Some(_) => LinesFound::default(),
+                None => return Ok(0),
+            }
+        } else {
+            match self.search_text(out)? {
+                Some(found) => found,
+                None => return Ok(0),
+            }
+        };
 
-        // is first line possibly binary?
-        for byte in &mut bin_check_buf[0..check_len] {
-            match byte {
-                0 | 1 => {
-                    if self.opt.debug {
-                        eprintln!("Debug: assuming binary file {}", self.file_path.display())
-                    }
-                    return Ok(());
+        let LinesFound { matched_lines, matches, .. } = found;
+        match self.opt.grep_report {
+            GrepReport::Lines => {
+                if self.opt.output_format == OutputFormat::Json && matched_lines > 0 {
+                    JsonEvent::End { path: &self.display_path, matched_lines, matches }.write(out)?;
                 }
-                _ => {
-                    continue;
+                if self.uses_heading() && matched_lines > 0 {
+                    // a blank line between the files
+                    writeln!(out).map_err(SfindError::Output)?;
+                }
+            }
+            GrepReport::FilesWithMatches => {
+                if matched_lines > 0 {
+                    self.print_path(out, None)?;
                 }
             }
+            GrepReport::FilesWithoutMatch => {
+                if matched_lines == 0 {
+                    self.print_path(out, None)?;
+                }
+            }
+            GrepReport::Count => {
+                self.print_path(out, Some((matched_lines, matches)))?;
+            }
         }
+        Ok(matched_lines)
+    }
 
-        file.rewind()?;
+    // write the lines to report to out, returns None for a binary file
+    fn search_text<W: Write>(&mut self, out: &mut W) -> Result<Option<LinesFound>> {
+        if self.opt.replace_diff || self.opt.replace_write {
+            return self.search_for_replace(out);
+        }
+        // with only negative patterns there is nothing to match across lines
+        if self.opt.grep_multiline && !self.patterns.patterns.is_empty() {
+            self.search_whole_file(out)
+        } else {
+            self.search_lines(out)
+        }
+    }
 
-        let mut reader = BufReader::with_capacity(1024*1024, file);
+    // the encoding to read the file in: --encoding, the byte order mark or the
+    // encoding the config gives the extension. None to work it out line by line
+    fn file_encoding(&self, start_bytes: &[u8]) -> Option<&'static Encoding> {
+        if self.opt.encoding.is_some() {
+            return self.opt.encoding;
+        }
+        if let Some((encoding, _)) = Encoding::for_bom(start_bytes) {
+            return Some(encoding);
+        }
+        let extension = self.file_path.extension()?.to_string_lossy().to_lowercase();
+        self.opt.extension_encodings.get(&extension).copied()
+    }
+
+    // is first line possibly binary?
+    fn is_binary(&self, bin_check_buf: &[u8], encoding: Option<&'static Encoding>) -> bool {
+        // UTF-16 text is full of NUL bytes
+        if self.opt.binary_mode == BinaryMode::Text || encoding.is_some_and(|encoding| !encoding.is_ascii_compatible()) {
+            return false;
+        }
+        let binary = bin_check_buf.iter().any(|&byte| byte == 0 || byte == 1);
+        if binary && self.opt.debug {
+            eprintln!("Debug: assuming binary file {}", self.file_path.display())
+        }
+        binary
+    }
+
+    // The contents of the file. With --decompress a file that starts with the
+    // magic bytes of a compression format is decompressed as it is read.
+    fn open_file(&self) -> io::Result<Box<dyn Read + 'caller>> {
+        match self.contents {
+            Some(contents) => Ok(Box::new(contents)),
+            None => Ok(Box::new(fs::File::open(self.file_path)?)),
+        }
+    }
+
+    fn open_contents(&self) -> io::Result<Box<dyn Read + 'caller>> {
+        let mut file = self.open_file()?;
+        if !self.opt.decompress {
+            return Ok(file);
+        }
+        let mut magic = vec![];
+        (&mut file).take(MAGIC_LEN as u64).read_to_end(&mut magic)?;
+        // put back the bytes read to find the compression
+        let file = Box::new(io::Cursor::new(magic.clone()).chain(file));
+        match Compression::from_magic(&magic) {
+            Some(compression) => {
+                if self.opt.debug {
+                    eprintln!("Debug: decompressing {} as {}", self.file_path.display(), compression.name());
+                }
+                match compression.checked_decoder(file)? {
+                    Some(decoder) => Ok(decoder),
+                    // the file only starts like a compressed file, search it as it is
+                    None => {
+                        if self.opt.debug {
+                            eprintln!("Debug: {} is not {}", self.file_path.display(), compression.name());
+                        }
+                        self.open_file()
+                    }
+                }
+            }
+            None => Ok(Box::new(file)),
+        }
+    }
+
+    fn read_contents(&self) -> Result<Vec<u8>> {
+        let mut bytes = vec![];
+        self.open_contents()
+            .and_then(|mut contents| contents.read_to_end(&mut bytes))
+            .map_err(|e| SfindError::io(self.file_path, e))?;
+        Ok(bytes)
+    }
+
+    fn search_lines<W: Write>(&mut self, out: &mut W) -> Result<Option<LinesFound>> {
+        let io_error = |e| SfindError::io(self.file_path, e);
+
+        let mut contents = self.open_contents().map_err(io_error)?;
+        let mut bin_check_buf = vec![];
+        contents.by_ref().take(1024).read_to_end(&mut bin_check_buf).map_err(io_error)?;
+
+        let encoding = self.file_encoding(&bin_check_buf);
+        if self.is_binary(&bin_check_buf, encoding) {
+            if self.opt.binary_mode == BinaryMode::Skip {
+                return Ok(None);
+            }
+            contents.read_to_end(&mut bin_check_buf).map_err(io_error)?;
+            return self.search_binary(out, &bin_check_buf);
+        }
+        if self.opt.debug {
+            if let Some(encoding) = encoding {
+                eprintln!("Debug: reading {} as {}", self.file_path.display(), encoding.name());
+            }
+        }
+
+        let mut file_lines = FileLines::new((&bin_check_buf[..]).chain(contents), encoding).map_err(io_error)?;
 
         let mut required_after = 0;
+        let mut found = LinesFound {
+            patterns_seen: vec![false; self.patterns.patterns.len()],
+            ..LinesFound::default()
+        };
 
-        loop {
-            let mut line_buf = vec![];
-            let len = reader.read_until(0x0a, &mut line_buf)
-                .map_err(|e| anyhow!("Error reading {} - {}", self.file_path.display(), e))?;
-            if len == 0 {
-                return Ok(());
+        while let Some((line_offset, line, encoding)) = file_lines.next_line().map_err(io_error)? {
+            self.encoding = encoding;
+
+            self.line_number += 1;
+
+            if self.patterns.none_of_patterns.iter().any(|regex| regex.is_match(&line)) {
+                // nothing from this file will be reported
+                found.disqualified = true;
+                break;
             }
 
-            let cow_line = {
-                let (cow_utf8, _encoding_used, had_errors) = UTF_8.decode(&line_buf);
-                if !had_errors {
-                    cow_utf8
-                } else {
-                    let (cow_1252, _encoding_used, _had_errors) = WINDOWS_1252.decode(&line_buf);
-                    cow_1252
+            match self.patterns.select_line(&line) {
+                None => {
+                    // line not selected
+                    if required_after > 0 {
+                        self.print_context_line(out, self.line_number, line_offset, "+", &line)?;
+                        required_after -= 1;
+                    } else if self.num_before > 0 {
+                        // only lines that have not been printed can come before the next match
+                        self.before_lines.push_back((line_offset, line.to_string()));
+                        if self.before_lines.len() > self.num_before {
+                            self.before_lines.pop_front();
+                        }
+                    }
+                }
+                Some(vec_m) => {
+                    if self.opt.debug {
+                        eprintln!("Debug: find_match: {:?}", vec_m);
+                    }
+                    for m in &vec_m {
+                        found.patterns_seen[m.pattern_index] = true;
+                    }
+                    match self.opt.grep_report {
+                        GrepReport::Lines => {}
+                        GrepReport::FilesWithMatches | GrepReport::FilesWithoutMatch => {
+                            found.matched_lines += 1;
+                            found.matches += vec_m.len();
+                            if self.patterns.file_decided(&found) {
+                                // no need to read the rest of the file
+                                break;
+                            }
+                            continue;
+                        }
+                        GrepReport::Count => {
+                            found.matched_lines += 1;
+                            found.matches += vec_m.len();
+                            continue;
+                        }
+                    }
+                    if self.opt.output_format == OutputFormat::Json && found.matched_lines == 0 {
+                        JsonEvent::Begin { path: &self.display_path }.write(out)?;
+                    }
+                    found.matched_lines += 1;
+                    found.matches += vec_m.len();
+
+                    let before_lines = mem::take(&mut self.before_lines);
+                    let line_number_base = self.line_number - before_lines.len();
+
+                    for (offset, (before_offset, before_line)) in before_lines.into_iter().enumerate() {
+                        self.print_context_line(out, line_number_base + offset, before_offset, "-", &before_line)?;
+                    }
+
+                    if self.patterns.replacement.is_some() {
+                        let (replaced, replacements) = self.patterns.replace_matches(&line, &vec_m);
+                        self.print_match(out, self.line_number, line_offset, &replaced, &replacements)?;
+                    } else {
+                        self.print_match(out, self.line_number, line_offset, &line, &vec_m)?;
+                    }
+
+                    required_after = self.num_after;
                 }
-            };
-            let mut line = cow_line.to_string();
-            if line.ends_with("\n") {
-                line.truncate(line.len()-1);
             }
+        }
 
-            self.line_number += 1;
+        Ok(Some(found))
+    }
+
+    // the text of the whole file and the encoding it was decoded from, None for a binary file
+    fn decode_file<'b>(&self, bytes: &'b [u8]) -> Option<(Cow<'b, str>, &'static Encoding)> {
+        let encoding = self.file_encoding(bytes);
+        if self.is_binary(&bytes[..bytes.len().min(1024)], encoding) {
+            return None;
+        }
+        Some(file_encoding::decode_text(bytes, encoding))
+    }
 
-            let vec_m = self.patterns.find_match(&line);
-            if vec_m.is_empty() {
-                // No matches
-                if self.num_before > 0 {
+    // --multiline: run the patterns over the whole file so that a match can cover several lines
+    fn search_whole_file<W: Write>(&mut self, out: &mut W) -> Result<Option<LinesFound>> {
+        let bytes = self.read_contents()?;
+        let (text, encoding) = match self.decode_file(&bytes) {
+            Some(decoded) => decoded,
+            None => return self.search_binary(out, &bytes),
+        };
+        self.encoding = encoding;
+        let text_offset = file_encoding::bom_len(&bytes);
+        let mut found = LinesFound {
+            patterns_seen: vec![false; self.patterns.patterns.len()],
+            ..LinesFound::default()
+        };
+        if self.patterns.none_of_patterns.iter().any(|regex| regex.is_match(&text)) {
+            found.disqualified = true;
+            return Ok(Some(found));
+        }
+
+        let lines = TextLines::new(&text);
+        let mut offsets = TextOffsets::new(encoding, &text, text_offset);
+        let spans = self.patterns.find_spans(&lines);
+        // the first line that has not been printed yet
+        let mut next_line = 0;
 
-                    self.before_lines.push_back(line.to_string());
-                    if self.before_lines.len() > self.num_before {
-                        self.before_lines.pop_front();
+        for (span_index, span) in spans.iter().enumerate() {
+            if self.opt.debug {
+                eprintln!("Debug: find_spans: {:?}", span);
+            }
+            for m in &span.matches {
+                found.patterns_seen[m.pattern_index] = true;
+            }
+            let span_lines = span.last_line - span.first_line + 1;
+            match self.opt.grep_report {
+                GrepReport::Lines => {}
+                GrepReport::FilesWithMatches | GrepReport::FilesWithoutMatch => {
+                    found.matched_lines += span_lines;
+                    found.matches += span.matches.len();
+                    if self.patterns.file_decided(&found) {
+                        break;
                     }
+                    continue;
                 }
-                if required_after > 0 {
-                    self.print_match_line(self.line_number, "+", &line);
-                    required_after -= 1;
+                GrepReport::Count => {
+                    found.matched_lines += span_lines;
+                    found.matches += span.matches.len();
+                    continue;
                 }
+            }
+            if self.opt.output_format == OutputFormat::Json && found.matched_lines == 0 {
+                JsonEvent::Begin { path: &self.display_path }.write(out)?;
+            }
+            found.matched_lines += span_lines;
+            found.matches += span.matches.len();
+
+            for line_index in span.first_line.saturating_sub(self.num_before).max(next_line)..span.first_line {
+                let (line_start, line) = lines.line(line_index);
+                let line_offset = offsets.offset(line_start);
+                self.print_context_line(out, line_index + 1, line_offset, "-", line)?;
+            }
+
+            if self.patterns.replacement.is_some() {
+                let span_offset = offsets.offset(lines.span_text(span.first_line, span.last_line).0);
+                self.print_replaced_span(out, &lines, span, span_offset)?;
             } else {
-                if self.opt.debug {
-                    eprintln!("Debug: find_match: {:?}", vec_m);
+                // -o finds the parts in the whole text as a capture group may cover several lines
+                let parts = if self.opt.grep_only_matching {
+                    self.patterns.only_matching(&text, &span.matches)
+                } else {
+                    span.matches.clone()
+                };
+                for line_index in span.first_line..=span.last_line {
+                    let (line_start, line) = lines.line(line_index);
+                    let line_matches = matches_in_line(&parts, line_start, line.len());
+                    let line_offset = offsets.offset(line_start);
+                    self.print_match(out, line_index + 1, line_offset, line, &line_matches)?;
                 }
+            }
 
-                let before_lines = mem::take(&mut self.before_lines);
-                let line_number_base = self.line_number - before_lines.len();
+            // stop the lines after at the next span
+            let after_end = spans
+                .get(span_index + 1)
+                .map_or(lines.len(), |next_span| next_span.first_line)
+                .min(span.last_line + 1 + self.num_after);
+            for line_index in span.last_line + 1..after_end {
+                let (line_start, line) = lines.line(line_index);
+                let line_offset = offsets.offset(line_start);
+                self.print_context_line(out, line_index + 1, line_offset, "+", line)?;
+            }
+            next_line = after_end.max(span.last_line + 1);
+        }
+
+        Ok(Some(found))
+    }
 
-                for (offset, before_line) in before_lines.into_iter().enumerate() {
-                    self.print_match_line(line_number_base + offset, "-", &before_line);
+    // the lines of the span after --replace, numbered from the first line of the span
+    fn print_replaced_span<W: Write>(
+        &mut self,
+        out: &mut W,
+        lines: &TextLines,
+        span: &MatchSpan,
+        span_offset: u64,
+    ) -> Result<()> {
+        let (span_start, span_text) = lines.span_text(span.first_line, span.last_line);
+        let (replaced, replacements) = self.patterns.replace_matches(span_text, &span.shifted_matches(span_start));
+        let replaced_lines = TextLines::new(&replaced);
+        for line_index in 0..replaced_lines.len() {
+            let (line_start, line) = replaced_lines.line(line_index);
+            let line_matches = matches_in_line(&replacements, line_start, line.len());
+            self.print_match(out, span.first_line + line_index + 1, span_offset, line, &line_matches)?;
+        }
+        Ok(())
+    }
+
+    // --binary=report and hex: search a binary file with each byte read as one character
+    fn search_binary<W: Write>(&mut self, out: &mut W, bytes: &[u8]) -> Result<Option<LinesFound>> {
+        if self.opt.binary_mode == BinaryMode::Skip {
+            return Ok(None);
+        }
+        // windows-1252 decodes every byte to one character
+        let text = WINDOWS_1252.decode_without_bom_handling(bytes).0;
+        self.encoding = WINDOWS_1252;
+        let mut found = LinesFound {
+            patterns_seen: vec![false; self.patterns.patterns.len()],
+            ..LinesFound::default()
+        };
+        if self.patterns.none_of_patterns.iter().any(|regex| regex.is_match(&text)) {
+            found.disqualified = true;
+            return Ok(Some(found));
+        }
+
+        let lines = TextLines::new(&text);
+        let mut text_matches = vec![];
+        for line_index in 0..lines.len() {
+            let (line_start, line) = lines.line(line_index);
+            let vec_m = match self.patterns.select_line(line) {
+                Some(vec_m) => vec_m,
+                None => continue,
+            };
+            for m in &vec_m {
+                found.patterns_seen[m.pattern_index] = true;
+            }
+            found.matched_lines += 1;
+            found.matches += vec_m.len();
+            text_matches.extend(vec_m.into_iter().map(|m| GrepMatch {
+                pattern_index: m.pattern_index,
+                start: line_start + m.start,
+                end: line_start + m.end,
+            }));
+        }
+
+        if self.opt.grep_report != GrepReport::Lines || found.matched_lines == 0 {
+            return Ok(Some(found));
+        }
+        let colours = self.colours;
+        match (self.opt.output_format, self.opt.binary_mode) {
+            (OutputFormat::Json, _) => {
+                JsonEvent::Begin { path: &self.display_path }.write(out)?;
+                for m in byte_matches(&text, &text_matches) {
+                    JsonEvent::BinaryMatch {
+                        path: &self.display_path,
+                        absolute_offset: m.start as u64,
+                        length: m.end - m.start,
+                        pattern_index: m.pattern_index,
+                        pattern: &self.patterns.pattern_texts[m.pattern_index],
+                    }
+                    .write(out)?;
                 }
+            }
+            (_, BinaryMode::Hex) => self.print_hex_dump(out, bytes, &byte_matches(&text, &text_matches))?,
+            _ => writeln!(out, "Binary file {} matches", colours.paint(&colours.file, &self.display_path))
+                .map_err(SfindError::Output)?,
+        }
+        Ok(Some(found))
+    }
 
-                self.print_match_line(self.line_number, ":", &colour_match_line(&line, &vec_m));
+    // --binary=hex: the rows of bytes around the matches with a group separator between the parts of the file
+    fn print_hex_dump<W: Write>(&self, out: &mut W, bytes: &[u8], matches: &[GrepMatch]) -> Result<()> {
+        let colours = self.colours;
+        let colon = colours.paint(&colours.separator, ":");
+        // with --heading the path is not repeated on each row
+        let path = if self.uses_heading() {
+            writeln!(out, "{}", colours.paint(&colours.file, &self.display_path)).map_err(SfindError::Output)?;
+            String::new()
+        } else {
+            format!("{}{}", colours.paint(&colours.file, &self.display_path), colon)
+        };
 
-                required_after = self.num_after;
+        for (index, range) in hex_dump_ranges(bytes.len(), matches).into_iter().enumerate() {
+            match &self.opt.group_separator {
+                Some(separator) if index > 0 && !separator.is_empty() => {
+                    writeln!(out, "{}", colours.paint(&colours.separator, separator)).map_err(SfindError::Output)?;
+                }
+                _ => {}
+            }
+            let range_matches: Vec<GrepMatch> =
+                matches.iter().filter(|m| m.start < range.end && m.end > range.start).cloned().collect();
+            for row_start in range.clone().step_by(HEX_ROW_LEN) {
+                let row = &bytes[row_start..(row_start + HEX_ROW_LEN).min(range.end)];
+                writeln!(
+                    out,
+                    "{path}{offset}{colon} {row}",
+                    offset = colours.paint(&colours.line_number, &format!("{:08x}", row_start)),
+                    row = hex_row(row, row_start, &range_matches, colours),
+                )
+                .map_err(SfindError::Output)?;
             }
         }
+        Ok(())
+    }
+
+    // --diff and --write: the changes --replace makes to the file as a unified diff or written back to it
+    fn search_for_replace<W: Write>(&mut self, out: &mut W) -> Result<Option<LinesFound>> {
+        if self.contents.is_some() {
+            // the changes cannot be written back into the archive
+            if self.opt.debug {
+                eprintln!("Debug: not replacing in archive member {}", self.display_path);
+            }
+            return Ok(None);
+        }
+        if self.opt.replace_write && self.opt.replace_backup && self.display_path.ends_with(".orig") {
+            // most likely a backup made by an earlier --write --backup
+            return Err(SfindError::Replace {
+                path: self.file_path.clone(),
+                reason: "it looks like a backup made by --backup, it is left as it is".to_string(),
+            });
+        }
+
+        let bytes = fs::read(self.file_path).map_err(|e| SfindError::io(self.file_path, e))?;
+        if self.opt.decompress && Compression::from_magic(&bytes).is_some() {
+            // the changes cannot be written back into the compressed file
+            if self.opt.debug {
+                eprintln!("Debug: not replacing in compressed file {}", self.display_path);
+            }
+            return Ok(None);
+        }
+        let (text, encoding) = match self.decode_file(&bytes) {
+            Some(decoded) => decoded,
+            None => return Ok(None),
+        };
+        let mut found = LinesFound {
+            patterns_seen: vec![false; self.patterns.patterns.len()],
+            ..LinesFound::default()
+        };
+        if self.patterns.none_of_patterns.iter().any(|regex| regex.is_match(&text)) {
+            found.disqualified = true;
+            return Ok(Some(found));
+        }
+
+        let lines = TextLines::new(&text);
+        let old_lines: Vec<&str> = (0..lines.len()).map(|line_index| lines.line(line_index).1).collect();
+        let mut changes = vec![];
+        let mut replacement_count = 0;
+
+        if self.opt.grep_multiline && !self.patterns.patterns.is_empty() {
+            for span in self.patterns.find_spans(&lines) {
+                for m in &span.matches {
+                    found.patterns_seen[m.pattern_index] = true;
+                }
+                found.matched_lines += span.last_line - span.first_line + 1;
+                found.matches += span.matches.len();
+
+                let (span_start, span_text) = lines.span_text(span.first_line, span.last_line);
+                let (replaced, replacements) = self.patterns.replace_matches(span_text, &span.shifted_matches(span_start));
+                if replaced != span_text {
+                    replacement_count += replacements.len();
+                    let replaced = replaced.strip_suffix('\n').unwrap_or(&replaced);
+                    changes.push(LineChange {
+                        first: span.first_line,
+                        old_count: span.last_line - span.first_line + 1,
+                        new_lines: if replaced.is_empty() { vec![] } else { replaced.split('\n').map(String::from).collect() },
+                    });
+                }
+            }
+        } else {
+            for (line_index, line) in old_lines.iter().enumerate() {
+                let vec_m = match self.patterns.select_line(line) {
+                    Some(vec_m) => vec_m,
+                    None => continue,
+                };
+                for m in &vec_m {
+                    found.patterns_seen[m.pattern_index] = true;
+                }
+                found.matched_lines += 1;
+                found.matches += vec_m.len();
+
+                let (replaced, replacements) = self.patterns.replace_matches(line, &vec_m);
+                if replaced != *line {
+                    replacement_count += replacements.len();
+                    changes.push(LineChange {
+                        first: line_index,
+                        old_count: 1,
+                        new_lines: replaced.split('\n').map(String::from).collect(),
+                    });
+                }
+            }
+        }
+
+        if !self.patterns.file_qualifies(&found) {
+            // only change the files that would be reported
+            return Ok(Some(found));
+        }
+
+        let no_newline_at_end = !text.is_empty() && !text.ends_with('\n');
+        if !self.opt.replace_write {
+            diff_output::write_unified_diff(out, &self.display_path, &old_lines, no_newline_at_end, &changes, encoding)?;
+        } else if !changes.is_empty() {
+            if !self.opt.replace_dry_run {
+                let new_text = diff_output::apply_changes(&old_lines, no_newline_at_end, &changes);
+                let contents = self.encode_text(&new_text, encoding, &bytes)?;
+                write_file::write_atomically(self.file_path, &contents, self.opt.replace_backup)?;
+            }
+            self.print_replacement_count(out, replacement_count)?;
+        }
+        Ok(Some(found))
+    }
+
+    // put the text back into the encoding the file was read in, keeping any byte order mark
+    fn encode_text(&self, text: &str, encoding: &'static Encoding, original: &[u8]) -> Result<Vec<u8>> {
+        let encoded = file_encoding::encode_text(text, encoding)
+            .map_err(|reason| SfindError::Replace { path: self.file_path.clone(), reason })?;
+
+        let mut contents = original[..file_encoding::bom_len(original) as usize].to_vec();
+        contents.extend_from_slice(&encoded);
+        Ok(contents)
+    }
+
+    // --write: the number of replacements made, or that would be made with --dry-run
+    fn print_replacement_count<W: Write>(&self, out: &mut W, count: usize) -> Result<()> {
+        let colours = self.colours;
+        writeln!(
+            out,
+            "{}{} {} {} {}",
+            colours.paint(&colours.file, &self.display_path),
+            colours.paint(&colours.separator, ":"),
+            if self.opt.replace_dry_run { "would replace" } else { "replaced" },
+            count,
+            if count == 1 { "match" } else { "matches" },
+        )
+        .map_err(SfindError::Output)
+    }
+
+    // for reports that list files, optionally with their count of matches
+    fn print_path<W: Write>(&self, out: &mut W, counts: Option<(usize, usize)>) -> Result<()> {
+        let colours = self.colours;
+        match (self.opt.output_format, counts) {
+            (OutputFormat::Json, None) => {
+                JsonEvent::File { path: &self.display_path }.write(out)
+            }
+            (OutputFormat::Json, Some((matched_lines, matches))) => {
+                JsonEvent::End { path: &self.display_path, matched_lines, matches }.write(out)
+            }
+            (_, None) => {
+                writeln!(out, "{}", colours.paint(&colours.file, &self.display_path))
+                    .map_err(SfindError::Output)
+            }
+            (_, Some((matched_lines, _))) => {
+                writeln!(
+                    out,
+                    "{}{}{}",
+                    colours.paint(&colours.file, &self.display_path),
+                    colours.paint(&colours.separator, ":"),
+                    matched_lines
+                )
+                .map_err(SfindError::Output)
+            }
+        }
+    }
+
+    fn print_match<W: Write>(
+        &mut self,
+        out: &mut W,
+        line_number: usize,
+        line_offset: u64,
+        line: &str,
+        matches: &[GrepMatch],
+    ) -> Result<()> {
+        self.start_line(out, line_number)?;
+        if self.opt.grep_only_matching {
+            // with --multiline the parts have been found in the whole text already
+            let parts = if self.opt.grep_multiline { matches.to_vec() } else { self.patterns.only_matching(line, matches) };
+            return self.print_only_matching(out, line_number, line_offset, line, &parts);
+        }
+
+        let column = match_column(line, matches);
+        let byte_offset = self.file_offset(line_offset, line, matches.first().map_or(0, |m| m.start));
+        match self.opt.output_format {
+            OutputFormat::Default => {
+                let coloured_line = self.colour_match(line, matches);
+                self.print_match_line(out, line_number, Some(column), byte_offset, ":", &coloured_line)
+            }
+            OutputFormat::Emacs => {
+                // emacs counts columns in characters
+                let byte_offset = self.opt.show_byte_offset.then_some(byte_offset);
+                let coloured_line = self.colour_match(line, matches);
+                self.print_column_line(out, line_number, column, byte_offset, ": ", &coloured_line)
+            }
+            OutputFormat::Vimgrep => {
+                // vim counts columns in bytes
+                if matches.is_empty() {
+                    // an inverted match
+                    let byte_offset = self.opt.show_byte_offset.then_some(byte_offset);
+                    return self.print_column_line(out, line_number, 1, byte_offset, ":", line);
+                }
+                for m in matches {
+                    let byte_offset = self.opt.show_byte_offset.then(|| self.file_offset(line_offset, line, m.start));
+                    let coloured_line = self.colour_match(line, std::slice::from_ref(m));
+                    self.print_column_line(out, line_number, m.start + 1, byte_offset, ":", &coloured_line)?;
+                }
+                Ok(())
+            }
+            OutputFormat::Json => {
+                JsonEvent::Match {
+                    path: &self.display_path,
+                    line_number,
+                    column: self.opt.show_column.then_some(column),
+                    byte_offset: self.opt.show_byte_offset.then_some(byte_offset),
+                    absolute_offset: line_offset,
+                    text: line,
+                    submatches: JsonSubmatch::from_matches(line, matches, &self.patterns.pattern_texts),
+                }
+                .write(out)
+            }
+        }
+    }
+
+    // the line with the matches coloured, after the patterns that matched for --show-patterns
+    fn colour_match(&self, line: &str, matches: &[GrepMatch]) -> String {
+        let coloured_line = colour_match_line(line, matches, self.colours);
+        if !self.opt.show_patterns || matches.is_empty() {
+            return coloured_line;
+        }
+
+        let colours = self.colours;
+        let names: Vec<_> = self
+            .patterns
+            .matched_patterns(matches)
+            .into_iter()
+            .map(|index| {
+                let colour = if colours.matches.is_empty() { "" } else { &colours.matches[index % colours.matches.len()] };
+                colours.paint(colour, &self.patterns.pattern_texts[index])
+            })
+            .collect();
+        format!("[{}] {}", names.join(", "), coloured_line)
+    }
+
+    // -o: each part of the line that matched on its own line
+    fn print_only_matching<W: Write>(
+        &self,
+        out: &mut W,
+        line_number: usize,
+        line_offset: u64,
+        line: &str,
+        parts: &[GrepMatch],
+    ) -> Result<()> {
+        for part in parts {
+            let text = &line[part.start..part.end];
+            let text_match = [GrepMatch { pattern_index: part.pattern_index, start: 0, end: text.len() }];
+            let coloured_text = self.colour_match(text, &text_match);
+            let column = match_column(line, std::slice::from_ref(part));
+            let byte_offset = self.file_offset(line_offset, line, part.start);
+            match self.opt.output_format {
+                OutputFormat::Default => {
+                    self.print_match_line(out, line_number, Some(column), byte_offset, ":", &coloured_text)?;
+                }
+                OutputFormat::Emacs => {
+                    let byte_offset = self.opt.show_byte_offset.then_some(byte_offset);
+                    self.print_column_line(out, line_number, column, byte_offset, ": ", &coloured_text)?;
+                }
+                OutputFormat::Vimgrep => {
+                    let byte_offset = self.opt.show_byte_offset.then_some(byte_offset);
+                    self.print_column_line(out, line_number, part.start + 1, byte_offset, ":", &coloured_text)?;
+                }
+                OutputFormat::Json => {
+                    JsonEvent::Match {
+                        path: &self.display_path,
+                        line_number,
+                        column: self.opt.show_column.then_some(column),
+                        byte_offset: self.opt.show_byte_offset.then_some(byte_offset),
+                        absolute_offset: line_offset,
+                        text,
+                        submatches: JsonSubmatch::from_matches(text, &text_match, &self.patterns.pattern_texts),
+                    }
+                    .write(out)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn print_context_line<W: Write>(
+        &mut self,
+        out: &mut W,
+        line_number: usize,
+        line_offset: u64,
+        sep: &str,
+        line: &str,
+    ) -> Result<()> {
+        self.start_line(out, line_number)?;
+        let coloured_line = self.colours.paint(&self.colours.context, line);
+        match self.opt.output_format {
+            OutputFormat::Default => {
+                self.print_match_line(out, line_number, None, line_offset, sep, &coloured_line)
+            }
+            OutputFormat::Emacs => {
+                // use grep's path-line-text so that compilation-mode does not see a match
+                let colours = self.colours;
+                let dash = colours.paint(&colours.separator, "-");
+                let byte_offset = if self.opt.show_byte_offset {
+                    format!("{}{}", colours.paint(&colours.line_number, &line_offset.to_string()), dash)
+                } else {
+                    String::new()
+                };
+                writeln!(
+                    out,
+                    "{path}{dash}{line_number}{dash}{byte_offset}{coloured_line}",
+                    path = colours.paint(&colours.file, &self.display_path),
+                    line_number = colours.paint(&colours.line_number, &line_number.to_string()),
+                )
+                .map_err(SfindError::Output)
+            }
+            OutputFormat::Vimgrep => {
+                // the quickfix list only wants matches
+                Ok(())
+            }
+            OutputFormat::Json => {
+                JsonEvent::Context { path: &self.display_path, line_number, text: line }.write(out)
+            }
+        }
+    }
+
+    // --heading prints the path once above the lines of each file
+    fn uses_heading(&self) -> bool {
+        self.opt.output_heading && self.opt.output_format == OutputFormat::Default
+    }
+
+    // Before each line is printed: the heading before the first line and the group
+    // separator between lines that are not next to each other when there is context.
+    fn start_line<W: Write>(&mut self, out: &mut W, line_number: usize) -> Result<()> {
+        let colours = self.colours;
+        if self.last_printed_line == 0 {
+            if self.uses_heading() {
+                writeln!(out, "{}", colours.paint(&colours.file, &self.display_path)).map_err(SfindError::Output)?;
+            }
+        } else if line_number > self.last_printed_line + 1
+            && (self.num_before > 0 || self.num_after > 0)
+            && matches!(self.opt.output_format, OutputFormat::Default | OutputFormat::Emacs)
+        {
+            match &self.opt.group_separator {
+                Some(separator) if !separator.is_empty() => {
+                    writeln!(out, "{}", colours.paint(&colours.separator, separator)).map_err(SfindError::Output)?;
+                }
+                _ => {}
+            }
+        }
+        self.last_printed_line = line_number;
+        Ok(())
+    }
+
+    fn print_column_line<W: Write>(
+        &self,
+        out: &mut W,
+        line_number: usize,
+        column: usize,
+        byte_offset: Option<u64>,
+        sep: &str,
+        line: &str,
+    ) -> Result<()> {
+        let colours = self.colours;
+        let colon = colours.paint(&colours.separator, ":");
+        let byte_offset = match byte_offset {
+            Some(byte_offset) => format!("{}{}", colon, colours.paint(&colours.line_number, &byte_offset.to_string())),
+            None => String::new(),
+        };
+        writeln!(
+            out,
+            "{path}{colon}{line_number}{colon}{column}{byte_offset}{sep}{line}",
+            path = colours.paint(&colours.file, &self.display_path),
+            line_number = colours.paint(&colours.line_number, &line_number.to_string()),
+            column = colours.paint(&colours.line_number, &column.to_string()),
+            sep = colours.paint(&colours.separator, sep),
+        )
+        .map_err(SfindError::Output)
+    }
+
+    // the offset in the file of index into text, when the text starts at text_offset in the file
+    fn file_offset(&self, text_offset: u64, text: &str, index: usize) -> u64 {
+        file_encoding::file_offset(self.encoding, text_offset, text, index)
     }
 
     const PADDING_SIZE: usize = 4;
 
-    fn print_match_line(&self, line_number: usize, sep: &str, line: &str) {
-        let path = self.file_path.display().to_string();
+    // column is only printed for matches, the byte offset is of the match or start of a context line
+    fn print_match_line<W: Write>(
+        &self,
+        out: &mut W,
+        line_number: usize,
+        column: Option<usize>,
+        byte_offset: u64,
+        sep: &str,
+        line: &str,
+    ) -> Result<()> {
+        let colours = self.colours;
+        // with --heading the path is not repeated on each line
+        let path = if self.uses_heading() {
+            String::new()
+        } else {
+            format!("{}{}", colours.paint(&colours.file, &self.display_path), colours.paint(&colours.separator, ":"))
+        };
+        let path_len = if self.uses_heading() { 0 } else { self.display_path.len() + 1 };
         let line_number = line_number.to_string();
 
-        // len of path + ":" + min 4 digits + sep + min-2-spaces
-        let prefix_len = path.len() + 1 + std::cmp::max(4, line_number.len()) + 1 + 2;
+        // the --column and --byte-offset fields after the line number
+        let mut fields = vec![];
+        if let (true, Some(column)) = (self.opt.show_column, column) {
+            fields.push(column.to_string());
+        }
+        if self.opt.show_byte_offset {
+            fields.push(byte_offset.to_string());
+        }
+        let fields_len: usize = fields.iter().map(|field| field.len() + 1).sum();
+        let fields: String = fields
+            .iter()
+            .map(|field| format!("{}{}", colours.paint(&colours.separator, sep), colours.paint(&colours.line_number, field)))
+            .collect();
+
+        // len of path + ":" + min 4 digits + fields + sep + min-2-spaces
+        let prefix_len = path_len + std::cmp::max(4, line_number.len()) + fields_len + 1 + 2;
         let padding_required = GrepInFile::PADDING_SIZE - (prefix_len % GrepInFile::PADDING_SIZE);
 
-        let padding: String = iter::repeat(if self.opt.debug { '·' } else { ' ' })
-            .take(padding_required)
+        let padding: String = iter::repeat_n(if self.opt.debug { '·' } else { ' ' }, padding_required)
             .collect();
 
-        println!(
-            "{colour_file}{path}{colour_end}:{colour_line}{line_number}{colour_end}{sep}{padding}{line}",
-            colour_file = GrepInFile::COLOUR_FILE,
-            colour_end = GrepInFile::COLOUR_END,
-            colour_line = GrepInFile::COLOUR_LINE
-        );
+        writeln!(
+            out,
+            "{path}{line_number}{fields}{sep}{padding}{line}",
+            line_number = colours.paint(&colours.line_number, &line_number),
+            sep = colours.paint(&colours.separator, sep),
+        )
+        .map_err(SfindError::Output)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_support::{test_options, ScratchFolder};
 
     #[test]
     fn quote_regex() {
@@ -268,4 +1440,349 @@ mod tests {
         assert_eq!(GrepPatterns::quote_regex("file.type"), "file\\.type");
         assert_eq!(GrepPatterns::quote_regex("*.type"), "\\*\\.type");
     }
+
+    fn grep_patterns(patterns: &[&str], negative_patterns: &[&str], invert_match: bool) -> GrepPatterns {
+        let to_regex = |pattern: &&str| Regex::new(pattern).unwrap();
+        let regexes: Vec<_> = patterns.iter().map(to_regex).collect();
+        GrepPatterns {
+            pattern_set: pattern_set(&regexes),
+            patterns: regexes,
+            pattern_texts: patterns.iter().map(|pattern| pattern.to_string()).collect(),
+            negative_patterns: negative_patterns.iter().map(to_regex).collect(),
+            invert_match,
+            all_of: false,
+            none_of_patterns: vec![],
+            replacement: None,
+            only_group: None,
+        }
+    }
+
+    #[test]
+    fn upper_case_patterns() {
+        assert!(has_upper_case("HashMap", false));
+        assert!(!has_upper_case("hashmap", false));
+        assert!(!has_upper_case(r"\S+\.rs", true));
+        assert!(has_upper_case(r"\S+\.Rs", true));
+        assert!(has_upper_case(r"\S", false));
+    }
+
+    // the text of each match in the line with the patterns of args
+    fn matched<'a>(args: &[&str], line: &'a str) -> Vec<&'a str> {
+        let patterns = GrepPatterns::new(&test_options(args)).unwrap();
+        patterns.find_match(line).iter().map(|m| &line[m.start..m.end]).collect()
+    }
+
+    #[test]
+    fn word_regexp() {
+        assert_eq!(matched(&["-w", "-f", "foo"], "foo food x.foo"), ["foo", "foo"]);
+        assert_eq!(matched(&["-w", "-f", "foo("], "call foo( x"), ["foo("]);
+        assert!(matched(&["-w", "-f", "foo("], "foo(x").is_empty());
+        assert!(matched(&["-w", "-r", r"\(x"], "foo(x)").is_empty());
+        assert_eq!(matched(&["-w", "-r", r"\(x"], "f (x)"), ["(x"]);
+    }
+
+    #[test]
+    fn line_regexp() {
+        assert_eq!(matched(&["-x", "-r", "fo+|bar"], "foo"), ["foo"]);
+        assert!(matched(&["-x", "-r", "fo+|bar"], "foo bar").is_empty());
+        assert!(matched(&["-x", "-f", "foo"], " foo").is_empty());
+    }
+
+    #[test]
+    fn smart_case() {
+        assert_eq!(matched(&["--smart-case", "-f", "map"], "Map map"), ["Map", "map"]);
+        assert_eq!(matched(&["--smart-case", "-f", "Map"], "Map map"), ["Map"]);
+        assert_eq!(matched(&["-f", "Map"], "Map map"), ["Map", "map"]);
+        assert_eq!(matched(&["-s", "-f", "map"], "Map map"), ["map"]);
+    }
+
+    #[test]
+    fn select_line() {
+        let patterns = grep_patterns(&["unwrap"], &["//.*unwrap"], false);
+        assert_eq!(patterns.select_line("x.unwrap();").unwrap().len(), 1);
+        assert!(patterns.select_line("// x.unwrap();").is_none());
+        assert!(patterns.select_line("x?;").is_none());
+
+        let patterns = grep_patterns(&[], &["^#"], false);
+        assert!(patterns.select_line("code").unwrap().is_empty());
+        assert!(patterns.select_line("# comment").is_none());
+    }
+
+    #[test]
+    fn select_inverted_line() {
+        let patterns = grep_patterns(&["unwrap"], &["//.*unwrap"], true);
+        assert!(patterns.select_line("x.unwrap();").is_none());
+        assert!(patterns.select_line("// x.unwrap();").unwrap().is_empty());
+        assert!(patterns.select_line("x?;").unwrap().is_empty());
+    }
+
+    #[test]
+    fn text_lines() {
+        let lines = TextLines::new("fn f(\n    self,\n)\n");
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines.line_index(0), 0);
+        assert_eq!(lines.line_index(5), 0);
+        assert_eq!(lines.line_index(6), 1);
+        assert_eq!(lines.line(1), (6, "    self,"));
+        assert_eq!(lines.line(2), (16, ")"));
+
+        let lines = TextLines::new("no newline");
+        assert_eq!(lines.len(), 1);
+        assert_eq!(lines.line(0), (0, "no newline"));
+    }
+
+    #[test]
+    fn find_spans() {
+        let text = "fn a(\n    self) {}\nfn b(x: u8) {}\nfn c(\n    self) {} // skip\n";
+        let lines = TextLines::new(text);
+        let patterns = grep_patterns(&[r"fn\s+\w+\(\s*\n\s*self"], &["skip"], false);
+        let spans = patterns.find_spans(&lines);
+        assert_eq!(spans.len(), 1);
+        assert_eq!((spans[0].first_line, spans[0].last_line), (0, 1));
+
+        let (line_start, line) = lines.line(1);
+        let line_matches = matches_in_line(&spans[0].matches, line_start, line.len());
+        assert_eq!((line_matches[0].start, line_matches[0].end), (0, 8));
+    }
+
+    #[test]
+    fn replace_matches() {
+        let mut patterns = grep_patterns(&[r"(?P<name>\w+)\.unwrap\(\)", r"(\w+)!"], &[], false);
+        patterns.replacement = Some("${1}?".to_string());
+        let line = "let x = y.unwrap() + z!(w);";
+        let (replaced, replacements) = patterns.replace_matches(line, &patterns.find_match(line));
+        assert_eq!(replaced, "let x = y? + z?(w);");
+        assert_eq!((replacements[0].start, replacements[0].end), (8, 10));
+        assert_eq!((replacements[1].start, replacements[1].end), (13, 15));
+    }
+
+    #[test]
+    fn only_matching() {
+        let mut patterns = grep_patterns(&[r"v(\d+)\.(\d+)", r"\d+\.\d+\.\d+"], &[], false);
+        let line = "from v1.2.3 to v2.0";
+        let parts = patterns.only_matching(line, &patterns.find_match(line));
+        let texts: Vec<_> = parts.iter().map(|part| &line[part.start..part.end]).collect();
+        // the second pattern's 1.2.3 overlaps v1.2 so only .3 is left of it
+        assert_eq!(texts, vec!["v1.2", ".3", "v2.0"]);
+
+        patterns.only_group = Some(2);
+        let parts = patterns.only_matching(line, &patterns.find_match(line));
+        let texts: Vec<_> = parts.iter().map(|part| &line[part.start..part.end]).collect();
+        assert_eq!(texts, vec!["2", "0"]);
+    }
+
+    #[test]
+    fn only_matching_across_lines() {
+        let mut patterns = grep_patterns(&[r"fn (\w+)\(\s*\n\s*(self)"], &[], false);
+        patterns.only_group = Some(2);
+        let text = "fn get(\n    self) {}\n";
+        let lines = TextLines::new(text);
+        let spans = patterns.find_spans(&lines);
+        let parts = patterns.only_matching(text, &spans[0].matches);
+        let (line_start, line) = lines.line(1);
+        let line_parts = matches_in_line(&parts, line_start, line.len());
+        assert_eq!(&line[line_parts[0].start..line_parts[0].end], "self");
+        assert!(matches_in_line(&parts, 0, lines.line(0).1.len()).is_empty());
+    }
+
+    #[test]
+    fn matched_patterns() {
+        let patterns = grep_patterns(&["old_api", "legacy", "unused"], &[], false);
+        let line = "legacy(old_api(), legacy)";
+        assert_eq!(patterns.matched_patterns(&patterns.find_match(line)), vec![0, 1]);
+    }
+
+    #[test]
+    fn pattern_set_matches() {
+        let mut patterns = grep_patterns(&["old_api", "(?i)LEGACY", r"\d+", "unused"], &[], false);
+        assert!(patterns.pattern_set.is_some());
+        let line = "legacy(old_api(), 42)";
+        let positions = |matches: Vec<GrepMatch>| -> Vec<_> {
+            matches.iter().map(|m| (m.pattern_index, m.start, m.end)).collect()
+        };
+        let with_set = positions(patterns.find_match(line));
+        assert_eq!(with_set, vec![(1, 0, 6), (0, 7, 14), (2, 18, 20)]);
+
+        patterns.pattern_set = None;
+        assert_eq!(positions(patterns.find_match(line)), with_set);
+        assert!(patterns.find_match("nothing here").is_empty());
+    }
+
+    #[test]
+    fn file_qualifies() {
+        let mut patterns = grep_patterns(&["tokio", "block_on"], &[], false);
+        patterns.all_of = true;
+        let mut found = LinesFound { patterns_seen: vec![true, false], ..LinesFound::default() };
+        assert!(!patterns.file_qualifies(&found));
+
+        found.patterns_seen[1] = true;
+        assert!(patterns.file_qualifies(&found));
+        assert!(patterns.file_decided(&found));
+
+        patterns.none_of_patterns.push(Regex::new("async_std").unwrap());
+        assert!(patterns.file_qualifies(&found));
+        assert!(!patterns.file_decided(&found));
+
+        found.disqualified = true;
+        assert!(!patterns.file_qualifies(&found));
+    }
+
+    #[test]
+    fn hex_dump() {
+        let text = WINDOWS_1252.decode_without_bom_handling(b"\xff\xfeab\0key=\xe9t\xe9").0;
+        let m = |start, end| GrepMatch { pattern_index: 0, start, end };
+        let matches = byte_matches(&text, &[m(text.find("key").unwrap(), text.len())]);
+        assert_eq!((matches[0].start, matches[0].end), (5, 12));
+
+        // rows that touch are merged
+        assert_eq!(hex_dump_ranges(200, &[m(40, 42), m(90, 91), m(150, 151)]), vec![16..112, 128..176]);
+        assert_eq!(hex_dump_ranges(110, &[m(100, 104)]), vec![80..110]);
+
+        assert_eq!(
+            hex_row(b"\xff\xfeab\0key=\xe9t\xe9", 0, &matches, &Colours::none()),
+            "ff fe 61 62 00 6b 65 79  3d e9 74 e9              |..ab.key=.t.|"
+        );
+    }
+
+    #[test]
+    fn binary_json() {
+        let opt = test_options(&["--json", "--binary=report", "-r", "key=."]);
+        let patterns = GrepPatterns::new(&opt).unwrap();
+        let colours = Colours::none();
+        let path = PathBuf::from("x.bin");
+        let mut out = vec![];
+        let matched_lines = GrepInFile::new(&opt, &path, &patterns, &colours)
+            .with_contents(b"ab\0key=\xe9t\nkey=1")
+            .search(&mut out)
+            .unwrap();
+        assert_eq!(matched_lines, 2);
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            concat!(
+                r#"{"type":"begin","path":"x.bin"}"#, "\n",
+                r#"{"type":"binary_match","path":"x.bin","absolute_offset":3,"length":5,"pattern_index":0,"pattern":"key=."}"#, "\n",
+                r#"{"type":"binary_match","path":"x.bin","absolute_offset":10,"length":5,"pattern_index":0,"pattern":"key=."}"#, "\n",
+                r#"{"type":"end","path":"x.bin","matched_lines":2,"matches":2}"#, "\n",
+            )
+        );
+    }
+
+    // the number of lines that matched in contents and the output, for a file named x.txt
+    fn grep_contents(args: &[&str], contents: &str) -> (usize, String) {
+        let opt = test_options(args);
+        let patterns = GrepPatterns::new(&opt).unwrap();
+        let colours = Colours::none();
+        let path = PathBuf::from("x.txt");
+        let mut out = vec![];
+        let matched_lines = GrepInFile::new(&opt, &path, &patterns, &colours)
+            .with_contents(contents.as_bytes())
+            .search(&mut out)
+            .unwrap();
+        (matched_lines, String::from_utf8(out).unwrap())
+    }
+
+    const CONTEXT_TEXT: &str = "a\nkey1\nb\nkey2\nc\nd\ne\nf\nkey3\ng\n";
+
+    #[test]
+    fn context_lines() {
+        // the text after the line number is padded so that it lines up
+        let grouped = concat!(
+            "x.txt:1-   a\n",
+            "x.txt:2:   key1\n",
+            // after key1 and before key2, printed once
+            "x.txt:3+   b\n",
+            "x.txt:4:   key2\n",
+            "x.txt:5+   c\n",
+            "--\n",
+            "x.txt:8-   f\n",
+            "x.txt:9:   key3\n",
+            "x.txt:10+   g\n",
+        );
+        for multiline in [&[][..], &["-U"]] {
+            let args = [&["-r", r"key\d", "-b1", "-a1"], multiline].concat();
+            assert_eq!(grep_contents(&args, CONTEXT_TEXT), (3, grouped.to_string()));
+
+            let args = [&["-r", r"key\d", "-b1", "-a1", "--no-group-separator"], multiline].concat();
+            assert_eq!(grep_contents(&args, CONTEXT_TEXT), (3, grouped.replace("--\n", "")));
+
+            // matches next to each other are one group
+            let args = [&["-r", r"key\d", "-a1"], multiline].concat();
+            assert_eq!(
+                grep_contents(&args, "key1\nkey2\nx\ny\n"),
+                (2, "x.txt:1:   key1\nx.txt:2:   key2\nx.txt:3+   x\n".to_string())
+            );
+        }
+    }
+
+    #[test]
+    fn heading() {
+        let expected = "x.txt\n1- a\n2: key1\n3+ b\n4: key2\n5+ c\n--\n8- f\n9: key3\n10+ g\n\n";
+        for multiline in [&[][..], &["-U"]] {
+            let args = [&["-r", r"key\d", "-b1", "-a1", "--heading"], multiline].concat();
+            assert_eq!(grep_contents(&args, CONTEXT_TEXT), (3, expected.to_string()));
+        }
+    }
+
+    #[test]
+    fn multiline_context() {
+        // the lines of a match across lines are all match lines
+        assert_eq!(
+            grep_contents(&["-U", "-r", r"key1\nb", "-b1", "-a1"], CONTEXT_TEXT),
+            (2, "x.txt:1-   a\nx.txt:2:   key1\nx.txt:3:   b\nx.txt:4+   key2\n".to_string())
+        );
+    }
+
+    #[test]
+    fn file_reports() {
+        let text = "key1\nkey2 key3\nx\n";
+        // -l stops reading at the first match
+        assert_eq!(grep_contents(&["-l", "-r", "key"], text), (1, "x.txt\n".to_string()));
+        assert_eq!(grep_contents(&["-L", "-r", "key"], text), (1, String::new()));
+        assert_eq!(grep_contents(&["-L", "-r", "none"], text), (0, "x.txt\n".to_string()));
+        assert_eq!(grep_contents(&["-c", "-r", "key"], text), (2, "x.txt:2\n".to_string()));
+        assert_eq!(grep_contents(&["-c", "-r", "none"], text), (0, "x.txt:0\n".to_string()));
+
+        // --none-of is looked for after the first match, to the end of the file
+        assert_eq!(grep_contents(&["-l", "-r", "key", "--none-of", "x"], text), (0, String::new()));
+        assert_eq!(grep_contents(&["-L", "-r", "key", "--none-of", "x"], text), (0, "x.txt\n".to_string()));
+        assert_eq!(grep_contents(&["-c", "-r", "key", "--none-of", "x"], text), (0, "x.txt:0\n".to_string()));
+        // so -l does not stop at the first match
+        assert_eq!(grep_contents(&["-l", "-r", "key", "--none-of", "z"], text), (2, "x.txt\n".to_string()));
+    }
+
+    #[test]
+    fn editor_formats() {
+        let text = "a\na key b key\nc\n";
+        // emacs has the column of the first match and grep's path-line-text context lines
+        assert_eq!(
+            grep_contents(&["--format", "emacs", "-r", "key", "-b1", "-a1"], text),
+            (1, "x.txt-1-a\nx.txt:2:3: a key b key\nx.txt-3-c\n".to_string())
+        );
+        // vimgrep has a line for each match and no context lines
+        assert_eq!(
+            grep_contents(&["--format", "vimgrep", "-r", "key", "-b1", "-a1"], text),
+            (1, "x.txt:2:3:a key b key\nx.txt:2:9:a key b key\n".to_string())
+        );
+    }
+
+    #[test]
+    fn backup_files() {
+        let folder = ScratchFolder::new("orig");
+        let path = folder.write("x.orig", "key=1\n");
+        let patterns_of = |opt: &CommandOptions| GrepPatterns::new(opt).unwrap();
+        let colours = Colours::none();
+
+        // with --backup a .orig file is reported and left as it is
+        let opt = test_options(&["-r", "key", "--replace", "value", "--write", "--backup"]);
+        let patterns = patterns_of(&opt);
+        let result = GrepInFile::new(&opt, &path, &patterns, &colours).search(&mut vec![]);
+        assert!(matches!(result, Err(SfindError::Replace { .. })));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "key=1\n");
+
+        // without it a .orig file is the user's own and is written to
+        let opt = test_options(&["-r", "key", "--replace", "value", "--write"]);
+        let patterns = patterns_of(&opt);
+        GrepInFile::new(&opt, &path, &patterns, &colours).search(&mut vec![]).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "value=1\n");
+    }
 }
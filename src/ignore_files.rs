@@ -0,0 +1,226 @@
+use std::collections::VecDeque;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+use regex::Regex;
+
+use crate::error::SfindError;
+use crate::glob::{glob_to_regex_pattern, GlobSyntax};
+
+// ignore files read from each folder, later files take priority
+pub const IGNORE_FILE_NAMES: &[&str] = &[".gitignore", ".ignore", ".sfindignore"];
+
+#[derive(Debug)]
+struct IgnoreRule {
+    regex: Regex,
+    negated: bool,
+    dir_only: bool,
+}
+
+// The ignore rules of one folder, linked to the rules of its parent folder.
+// Paths are matched relative to the folder that the rules were read from.
+#[derive(Debug)]
+pub struct IgnoreDir {
+    parent: Option<Arc<IgnoreDir>>,
+    dir_name: String,
+    rules: Vec<IgnoreRule>,
+}
+
+impl IgnoreRule {
+    fn parse(line: &str) -> Result<Option<IgnoreRule>, SfindError> {
+        if line.is_empty() || line.starts_with('#') {
+            return Ok(None);
+        }
+
+        // trailing spaces are ignored unless quoted with a backslash
+        let mut pattern = line.trim_end_matches(['\r', '\n']);
+        while pattern.ends_with(' ') && !pattern.ends_with("\\ ") {
+            pattern = &pattern[..pattern.len() - 1];
+        }
+
+        let negated = pattern.starts_with('!');
+        if negated {
+            pattern = &pattern[1..];
+        }
+
+        let dir_only = pattern.ends_with('/');
+        if dir_only {
+            pattern = &pattern[..pattern.len() - 1];
+        }
+        if pattern.is_empty() {
+            return Ok(None);
+        }
+
+        // a "/" at the start or in the middle anchors the pattern to the folder
+        let anchored = pattern.contains('/');
+        let pattern = pattern.strip_prefix('/').unwrap_or(pattern);
+
+        let mut regex_pattern = String::from("^");
+        if !anchored {
+            regex_pattern.push_str("(?:.*/)?");
+        }
+        regex_pattern.push_str(&glob_to_regex_pattern(pattern, GlobSyntax::Ignore));
+        regex_pattern.push('$');
+
+        match Regex::new(&regex_pattern) {
+            Ok(regex) => Ok(Some(IgnoreRule { regex, negated, dir_only })),
+            Err(e) => Err(SfindError::BadPattern { pattern: line.to_string(), source: e }),
+        }
+    }
+
+    fn is_match(&self, rel_path: &str, is_dir: bool) -> bool {
+        (is_dir || !self.dir_only) && self.regex.is_match(rel_path)
+    }
+}
+
+impl IgnoreDir {
+    // read the ignore files found in folder and stack them on top of parent
+    pub fn new(
+        parent: Option<Arc<IgnoreDir>>,
+        folder: &Path,
+        errors: &mut VecDeque<SfindError>,
+    ) -> Arc<IgnoreDir> {
+        // folders such as "." need to be canonicalized to find their name
+        let dir_name = match folder.file_name() {
+            Some(name) => Some(name.to_os_string()),
+            None => fs::canonicalize(folder)
+                .ok()
+                .and_then(|folder| folder.file_name().map(|name| name.to_os_string())),
+        };
+        let dir_name = dir_name
+            .and_then(|name| name.into_string().ok())
+            .unwrap_or_default();
+
+        let mut rules = vec![];
+        // the top of a git repo can have extra rules that are not checked in,
+        // a worktree or submodule has a .git file that points to its repo instead
+        if folder.join(".git").is_dir() {
+            Self::read_rules(&folder.join(".git/info/exclude"), &mut rules, errors);
+        }
+        for ignore_file_name in IGNORE_FILE_NAMES {
+            Self::read_rules(&folder.join(ignore_file_name), &mut rules, errors);
+        }
+
+        Arc::new(IgnoreDir { parent, dir_name, rules })
+    }
+
+    // build the rules of the folders above folder up to the top of the git repo
+    // that contains folder, or up to the top of the file system outside a repo
+    pub fn new_for_ancestors(folder: &Path, errors: &mut VecDeque<SfindError>) -> Option<Arc<IgnoreDir>> {
+        let folder = fs::canonicalize(folder).ok()?;
+        let git_root = folder.ancestors().find(|ancestor| ancestor.join(".git").exists());
+
+        let ancestors: Vec<&Path> = folder
+            .ancestors()
+            .skip(1)
+            .take_while(|ancestor| git_root.is_none_or(|git_root| ancestor.starts_with(git_root)))
+            .collect();
+
+        ancestors
+            .into_iter()
+            .rev()
+            .fold(None, |parent, ancestor| Some(IgnoreDir::new(parent, ancestor, errors)))
+    }
+
+    fn read_rules(ignore_path: &Path, rules: &mut Vec<IgnoreRule>, errors: &mut VecDeque<SfindError>) {
+        match fs::read_to_string(ignore_path) {
+            Ok(contents) => {
+                for line in contents.lines() {
+                    match IgnoreRule::parse(line) {
+                        Ok(Some(rule)) => rules.push(rule),
+                        Ok(None) => {}
+                        Err(e) => errors.push_back(e),
+                    }
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => {
+                errors.push_back(SfindError::io(ignore_path, e));
+            }
+        }
+    }
+
+    // name is a file or folder inside the folder these rules belong to
+    pub fn is_ignored(&self, name: &str, is_dir: bool) -> bool {
+        let mut rel_path = name.to_string();
+        let mut ignore_dir = Some(self);
+
+        while let Some(dir) = ignore_dir {
+            // the last matching rule wins and rules in deeper folders win
+            if let Some(rule) = dir.rules.iter().rev().find(|rule| rule.is_match(&rel_path, is_dir)) {
+                return !rule.negated;
+            }
+            rel_path = format!("{}/{}", dir.dir_name, rel_path);
+            ignore_dir = dir.parent.as_deref();
+        }
+
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ignore_dir(parent: Option<Arc<IgnoreDir>>, dir_name: &str, lines: &[&str]) -> Arc<IgnoreDir> {
+        Arc::new(IgnoreDir {
+            parent,
+            dir_name: dir_name.to_string(),
+            rules: lines.iter().filter_map(|line| IgnoreRule::parse(line).unwrap()).collect(),
+        })
+    }
+
+    #[test]
+    fn comments_and_blank_lines() {
+        assert!(IgnoreRule::parse("").unwrap().is_none());
+        assert!(IgnoreRule::parse("# comment").unwrap().is_none());
+        assert!(IgnoreRule::parse("!").unwrap().is_none());
+        assert!(IgnoreRule::parse("\\#file").unwrap().is_some());
+    }
+
+    #[test]
+    fn basename_patterns() {
+        let dir = ignore_dir(None, "top", &["*.o", "target/", "build  "]);
+        assert!(dir.is_ignored("main.o", false));
+        assert!(!dir.is_ignored("main.c", false));
+        assert!(dir.is_ignored("target", true));
+        assert!(!dir.is_ignored("target", false));
+        assert!(dir.is_ignored("build", true));
+    }
+
+    #[test]
+    fn anchored_patterns() {
+        let top = ignore_dir(None, "top", &["/generated", "doc/*.html"]);
+        let sub = ignore_dir(Some(top.clone()), "doc", &[]);
+        assert!(top.is_ignored("generated", true));
+        assert!(sub.is_ignored("index.html", false));
+
+        let src = ignore_dir(Some(top), "src", &[]);
+        assert!(!src.is_ignored("generated", true));
+        assert!(!src.is_ignored("index.html", false));
+    }
+
+    #[test]
+    fn negated_patterns() {
+        let top = ignore_dir(None, "top", &["*.log", "!keep.log"]);
+        assert!(top.is_ignored("debug.log", false));
+        assert!(!top.is_ignored("keep.log", false));
+
+        let sub = ignore_dir(Some(top), "logs", &["!debug.log"]);
+        assert!(!sub.is_ignored("debug.log", false));
+        assert!(sub.is_ignored("trace.log", false));
+    }
+
+    #[test]
+    fn double_star_patterns() {
+        let top = ignore_dir(None, "top", &["**/cache", "out/**"]);
+        let sub = ignore_dir(Some(top.clone()), "a", &[]);
+        assert!(top.is_ignored("cache", true));
+        assert!(sub.is_ignored("cache", true));
+        assert!(!top.is_ignored("out", true));
+
+        let out = ignore_dir(Some(top), "out", &[]);
+        assert!(out.is_ignored("file.txt", false));
+    }
+}
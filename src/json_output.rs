@@ -0,0 +1,169 @@
+use std::io::{self, Write};
+
+use serde;
+use serde_json;
+
+use crate::error::SfindError;
+use crate::grep_in_file::GrepMatch;
+use crate::SearchSummary;
+
+// One line of --json output. Every event has a "type" field naming the variant.
+#[derive(serde::Serialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum JsonEvent<'a> {
+    // a file found when there are no patterns to grep for
+    File {
+        path: &'a str,
+    },
+    // before the first line reported from a file
+    Begin {
+        path: &'a str,
+    },
+    Match {
+        path: &'a str,
+        line_number: usize,
+        // --column, of the first match in characters
+        #[serde(skip_serializing_if = "Option::is_none")]
+        column: Option<usize>,
+        // --byte-offset, of the first match in the file
+        #[serde(skip_serializing_if = "Option::is_none")]
+        byte_offset: Option<u64>,
+        // offset of the start of the line in the file
+        absolute_offset: u64,
+        text: &'a str,
+        submatches: Vec<JsonSubmatch<'a>>,
+    },
+    Context {
+        path: &'a str,
+        line_number: usize,
+        text: &'a str,
+    },
+    // --binary=report and hex: a match in a binary file, which has no lines
+    BinaryMatch {
+        path: &'a str,
+        // offset of the match in the file
+        absolute_offset: u64,
+        // the number of bytes that matched
+        length: usize,
+        pattern_index: usize,
+        pattern: &'a str,
+    },
+    // after the last line reported from a file
+    End {
+        path: &'a str,
+        matched_lines: usize,
+        matches: usize,
+    },
+    Summary {
+        files_searched: usize,
+        files_matched: usize,
+        lines_matched: usize,
+        errors: usize,
+    },
+}
+
+#[derive(serde::Serialize, Debug)]
+pub struct JsonSubmatch<'a> {
+    pub pattern_index: usize,
+    // the fixed string or regex that matched
+    pub pattern: &'a str,
+    // byte offsets into the text of the line
+    pub start: usize,
+    pub end: usize,
+    pub text: &'a str,
+}
+
+impl<'a> JsonSubmatch<'a> {
+    pub fn from_matches(line: &'a str, matches: &[GrepMatch], pattern_texts: &'a [String]) -> Vec<JsonSubmatch<'a>> {
+        matches
+            .iter()
+            .map(|m| JsonSubmatch {
+                pattern_index: m.pattern_index,
+                pattern: &pattern_texts[m.pattern_index],
+                start: m.start,
+                end: m.end,
+                text: &line[m.start..m.end],
+            })
+            .collect()
+    }
+}
+
+impl<'a> JsonEvent<'a> {
+    pub fn summary(summary: &SearchSummary) -> JsonEvent<'a> {
+        JsonEvent::Summary {
+            files_searched: summary.files_searched,
+            files_matched: summary.files_matched,
+            lines_matched: summary.lines_matched,
+            errors: summary.errors,
+        }
+    }
+
+    pub fn write<W: Write>(&self, out: &mut W) -> Result<(), SfindError> {
+        serde_json::to_writer(&mut *out, self).map_err(|e| SfindError::Output(io::Error::from(e)))?;
+        writeln!(out).map_err(SfindError::Output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn match_event() {
+        let line = "let x = foo(bar);";
+        let pattern_texts = vec!["fo+".to_string(), "bar".to_string()];
+        let matches = vec![
+            GrepMatch { pattern_index: 0, start: 8, end: 11 },
+            GrepMatch { pattern_index: 1, start: 12, end: 15 },
+        ];
+        let event = JsonEvent::Match {
+            path: "src/x.rs",
+            line_number: 3,
+            column: None,
+            byte_offset: None,
+            absolute_offset: 40,
+            text: line,
+            submatches: JsonSubmatch::from_matches(line, &matches, &pattern_texts),
+        };
+
+        let mut out = vec![];
+        event.write(&mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            concat!(
+                r#"{"type":"match","path":"src/x.rs","line_number":3,"absolute_offset":40,"#,
+                r#""text":"let x = foo(bar);","submatches":["#,
+                r#"{"pattern_index":0,"pattern":"fo+","start":8,"end":11,"text":"foo"},"#,
+                r#"{"pattern_index":1,"pattern":"bar","start":12,"end":15,"text":"bar"}]}"#,
+                "\n"
+            )
+        );
+    }
+
+    #[test]
+    fn match_event_with_position() {
+        let event = JsonEvent::Match {
+            path: "x",
+            line_number: 1,
+            column: Some(5),
+            byte_offset: Some(4),
+            absolute_offset: 0,
+            text: "abc déf",
+            submatches: vec![],
+        };
+        let mut out = vec![];
+        event.write(&mut out).unwrap();
+        assert!(String::from_utf8(out).unwrap().contains(r#""line_number":1,"column":5,"byte_offset":4,"#));
+    }
+
+    #[test]
+    fn summary_event() {
+        let summary = SearchSummary { files_searched: 4, files_matched: 2, lines_matched: 7, errors: 0 };
+        let mut out = vec![];
+        JsonEvent::summary(&summary).write(&mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "{\"type\":\"summary\",\"files_searched\":4,\"files_matched\":2,\"lines_matched\":7,\"errors\":0}\n"
+        );
+    }
+}
@@ -1,37 +1,159 @@
-use anyhow::Result;
+use std::io::{self, Write};
+
+pub mod error;
+pub use error::SfindError;
+
+type Result<T> = std::result::Result<T, SfindError>;
 
 pub mod find_files;
-pub use find_files::FindFiles;
+pub use find_files::{FindFiles, FindPatterns, FolderQueue, FoundFile};
+
+pub mod ignore_files;
+pub use ignore_files::IgnoreDir;
+
+pub mod glob;
 
 pub mod grep_in_file;
 pub use grep_in_file::{GrepInFile, GrepPatterns};
 
+pub mod parallel_search;
+
+pub mod json_output;
+pub use json_output::JsonEvent;
+
+pub mod diff_output;
+
+pub mod write_file;
+
+pub mod file_encoding;
+
+pub mod decompress;
+
+pub mod archive;
+
 pub mod command_options;
-pub use command_options::CommandOptions;
+pub use command_options::{BinaryMode, CommandOptions, GrepReport, OutputFormat};
 
 pub mod config_json;
 pub use config_json::AppConfig;
 
-pub fn run(opt: CommandOptions, cfg: AppConfig) -> Result<()> {
-    if opt.fixed_strings.is_empty() && opt.regex_patterns.is_empty() {
+pub mod colours;
+pub use colours::Colours;
+
+#[cfg(test)]
+mod test_support;
+
+// Called with each error that does not stop the search, such as a file that
+// cannot be read, from whichever thread found it
+pub type OnError = dyn Fn(&SfindError) + Sync;
+
+// what happened during a search, used to set the exit code
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SearchSummary {
+    pub files_searched: usize,
+    pub files_matched: usize,
+    pub lines_matched: usize,
+    pub errors: usize,
+}
+
+impl SearchSummary {
+    pub fn add(&mut self, other: &SearchSummary) {
+        self.files_searched += other.files_searched;
+        self.files_matched += other.files_matched;
+        self.lines_matched += other.lines_matched;
+        self.errors += other.errors;
+    }
+
+    pub fn report_error(&mut self, error: &SfindError, on_error: &OnError) {
+        on_error(error);
+        self.errors += 1;
+    }
+}
+
+pub fn run(mut opt: CommandOptions, cfg: AppConfig, on_error: &OnError) -> Result<SearchSummary> {
+    opt.apply_config(&cfg.config);
+
+    let patterns = if !opt.has_patterns() {
         // just print the files that are found
-        for path in FindFiles::new(&opt, &cfg.config) {
-            println!("{}", path.display());
-        }
+        None
+    } else {
+        Some(GrepPatterns::new(&opt)?)
+    };
+
+    // bad file name patterns are reported once, before any searching
+    let find_patterns = FindPatterns::new(&opt, &cfg.config)?;
+
+    let colours = if opt.colour_output { cfg.colours()? } else { Colours::none() };
+
+    let summary = if opt.threads > 1 {
+        parallel_search::run(&opt, &find_patterns, patterns.as_ref(), &colours, &mut io::stdout(), on_error)?
     } else {
-        let patterns = GrepPatterns::new(&opt)?;
+        let mut summary = SearchSummary::default();
+        let mut out = io::stdout().lock();
+        for found in FindFiles::new(&opt, &find_patterns) {
+            match found {
+                Ok(found) => search_file(&opt, patterns.as_ref(), &colours, &found, &mut out, &mut summary, on_error)?,
+                Err(e) => summary.report_error(&e, on_error),
+            }
+        }
+        summary
+    };
+
+    if opt.output_format == OutputFormat::Json {
+        JsonEvent::summary(&summary).write(&mut io::stdout().lock())?;
+    }
+    Ok(summary)
+}
 
-        // search inside each found file
-        for path in FindFiles::new(&opt, &cfg.config) {
+// write the path of the file or, when there are patterns, the lines that match.
+// Only errors writing to out are returned, others are passed to on_error.
+pub fn search_file<W: Write>(
+    opt: &CommandOptions,
+    patterns: Option<&GrepPatterns>,
+    colours: &Colours,
+    found: &FoundFile,
+    out: &mut W,
+    summary: &mut SearchSummary,
+    on_error: &OnError,
+) -> Result<()> {
+    let path = found.path();
+    summary.files_searched += 1;
+    match patterns {
+        None => {
+            if opt.output_format == OutputFormat::Json {
+                JsonEvent::File { path: &path.display().to_string() }.write(out)?;
+            } else {
+                writeln!(out, "{}", colours.paint(&colours.file, &path.display().to_string()))
+                    .map_err(SfindError::Output)?;
+            }
+            summary.files_matched += 1;
+        }
+        Some(patterns) => {
+            // search inside the found file
             if opt.debug {
-                println!("grep_in_file {}", path.display());
+                eprintln!("Debug: grep_in_file {}", path.display());
             }
-            let mut grep_in_file = GrepInFile::new(&opt, &path, &patterns);
-            if let Err(e) = grep_in_file.search() {
-                eprintln!("{}", e);
+            let mut grep_in_file = GrepInFile::new(opt, path, patterns, colours);
+            if let Some(contents) = found.contents() {
+                grep_in_file = grep_in_file.with_contents(contents);
+            }
+            match grep_in_file.search(out) {
+                Err(e @ SfindError::Output(_)) => return Err(e),
+                Err(e) => summary.report_error(&e, on_error),
+                Ok(matched_lines) => {
+                    // -L selects the files that do not match
+                    let selected = if opt.grep_report == GrepReport::FilesWithoutMatch {
+                        matched_lines == 0
+                    } else {
+                        matched_lines > 0
+                    };
+                    if selected {
+                        summary.files_matched += 1;
+                    }
+                    summary.lines_matched += matched_lines;
+                }
             }
         }
     }
-
     Ok(())
 }
@@ -1,27 +1,42 @@
 use std::env;
+use std::io::{self, Write};
 use std::process::ExitCode;
-use clap;
+
+// exit codes follow grep's convention
+const EXIT_NO_MATCH: u8 = 1;
+const EXIT_ERROR: u8 = 2;
 
 fn main() -> ExitCode {
     let args: Vec<String> = env::args().collect();
 
     let cmd_opt = match sfind::CommandOptions::new(&args) {
         Ok(opt) => opt,
-        Err(error) => {
-            // there must be a better way to see if the text is from clap...
+        Err(sfind::SfindError::Usage(error)) => {
             use clap::error::ErrorKind;
-            match error.downcast_ref::<clap::error::Error>() {
-                Some(error) if matches!(error.kind(),
-                    ErrorKind::DisplayHelp | ErrorKind::UnknownArgument |
-                    ErrorKind::TooFewValues | ErrorKind::InvalidValue) => {
-                        eprintln!("{error}")
-                    }
-                Some(error) => {
-                        eprintln!("Error: {error}\nkind: {:?}", error.kind())
+            match error.kind() {
+                ErrorKind::DisplayHelp | ErrorKind::DisplayVersion => {
+                    // the reader may have gone, for example sfind --help | head
+                    match write!(io::stdout().lock(), "{error}") {
+                        Err(e) if e.kind() != io::ErrorKind::BrokenPipe => {
+                            eprintln!("Error: {e}");
+                            return ExitCode::from(EXIT_ERROR);
+                        }
+                        _ => return ExitCode::SUCCESS,
                     }
-                None => eprintln!("Error: {error}")
                 }
-            return ExitCode::from(1);
+                ErrorKind::UnknownArgument | ErrorKind::ArgumentConflict |
+                ErrorKind::TooFewValues | ErrorKind::InvalidValue => {
+                    eprintln!("{error}")
+                }
+                _ => {
+                    eprintln!("Error: {error}\nkind: {:?}", error.kind())
+                }
+            }
+            return ExitCode::from(EXIT_ERROR);
+        }
+        Err(error) => {
+            eprintln!("Error: {error}");
+            return ExitCode::from(EXIT_ERROR);
         }
     };
 
@@ -33,7 +48,7 @@ fn main() -> ExitCode {
         Ok(cfg) => cfg,
         Err(error) => {
             eprintln!("Error: {error}");
-            return ExitCode::from(1);
+            return ExitCode::from(EXIT_ERROR);
         }
     };
 
@@ -41,7 +56,7 @@ fn main() -> ExitCode {
         match cfg.save_default_config() {
             Err(e) => {
                 eprintln!("Error: Failed to save default config - {}", e);
-                return ExitCode::from(1);
+                return ExitCode::from(EXIT_ERROR);
             }
             Ok(()) => {
                 return ExitCode::SUCCESS;
@@ -57,11 +72,19 @@ fn main() -> ExitCode {
         dbg!(&cfg);
     };
 
-    match sfind::run(cmd_opt, cfg) {
-        Ok(_) => ExitCode::SUCCESS,
+    // the search goes on after these errors, they only change the exit code
+    let report_error = |error: &sfind::SfindError| eprintln!("Error: {error}");
+    match sfind::run(cmd_opt, cfg, &report_error) {
+        Ok(summary) if summary.errors > 0 => ExitCode::from(EXIT_ERROR),
+        Ok(summary) if summary.files_matched > 0 => ExitCode::SUCCESS,
+        Ok(_) => ExitCode::from(EXIT_NO_MATCH),
+        // the reader has gone, for example sfind -l ... | head
+        Err(sfind::SfindError::Output(error)) if error.kind() == io::ErrorKind::BrokenPipe => {
+            ExitCode::SUCCESS
+        }
         Err(error) => {
             eprintln!("Error: {error}");
-            ExitCode::from(1)
+            ExitCode::from(EXIT_ERROR)
         }
     }
 }
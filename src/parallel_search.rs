@@ -0,0 +1,256 @@
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+
+use crate::{search_file, Colours, CommandOptions, FindFiles, FindPatterns, FolderQueue, FoundFile, GrepPatterns, OnError, SearchSummary, SfindError};
+
+type Result<T> = std::result::Result<T, SfindError>;
+
+// Search using opt.threads threads. The output of each file is collected
+// before it is written to out so that the lines of two files are never mixed.
+pub fn run<W: Write + Send>(
+    opt: &CommandOptions,
+    find_patterns: &FindPatterns,
+    patterns: Option<&GrepPatterns>,
+    colours: &Colours,
+    out: &mut W,
+    on_error: &OnError,
+) -> Result<SearchSummary> {
+    if opt.ordered_output {
+        run_ordered(opt, find_patterns, patterns, colours, out, on_error)
+    } else {
+        run_unordered(opt, find_patterns, patterns, colours, out, on_error)
+    }
+}
+
+// opt.threads threads find folders from a shared queue and send the files they
+// find to opt.threads more threads that search them, so that all the threads
+// still search the files of a folder that has most of them
+fn run_unordered<W: Write + Send>(
+    opt: &CommandOptions,
+    find_patterns: &FindPatterns,
+    patterns: Option<&GrepPatterns>,
+    colours: &Colours,
+    out: &mut W,
+    on_error: &OnError,
+) -> Result<SearchSummary> {
+    let folders = Arc::new(FolderQueue::new(opt));
+    // bounded so that the files read from archives do not pile up in memory
+    let (found_tx, found_rx) = mpsc::sync_channel::<FoundFile>(opt.threads);
+    let found_rx = Arc::new(Mutex::new(found_rx));
+    let out = Mutex::new(out);
+
+    thread::scope(|scope| {
+        let finders: Vec<_> = (0..opt.threads)
+            .map(|_| {
+                let folders = folders.clone();
+                let found_tx = found_tx.clone();
+                scope.spawn(move || {
+                    let mut summary = SearchSummary::default();
+                    for found in FindFiles::new_shared(opt, find_patterns, folders) {
+                        match found {
+                            Ok(found) => {
+                                // the searchers have all stopped on an error
+                                if found_tx.send(found).is_err() {
+                                    break;
+                                }
+                            }
+                            Err(e) => summary.report_error(&e, on_error),
+                        }
+                    }
+                    summary
+                })
+            })
+            .collect();
+
+        let searchers: Vec<_> = (0..opt.threads)
+            .map(|_| {
+                let found_rx = found_rx.clone();
+                let out = &out;
+                scope.spawn(move || -> Result<SearchSummary> {
+                    let mut summary = SearchSummary::default();
+                    let mut buffer = vec![];
+                    loop {
+                        let next_found = found_rx.lock().unwrap().recv();
+                        let Ok(found) = next_found else { break };
+
+                        search_file(opt, patterns, colours, &found, &mut buffer, &mut summary, on_error)?;
+                        if !buffer.is_empty() {
+                            out.lock().unwrap().write_all(&buffer).map_err(SfindError::Output)?;
+                            buffer.clear();
+                        }
+                    }
+                    Ok(summary)
+                })
+            })
+            .collect();
+        // only the threads hold these now so the channel closes when either side finishes
+        drop(found_tx);
+        drop(found_rx);
+
+        let mut summary = SearchSummary::default();
+        for finder in finders {
+            summary.add(&finder.join().unwrap());
+        }
+        // wait for all the searchers before reporting the first error
+        let results: Vec<Result<SearchSummary>> = searchers
+            .into_iter()
+            .map(|searcher| searcher.join().unwrap())
+            .collect();
+        for result in results {
+            summary.add(&result?);
+        }
+        Ok(summary)
+    })
+}
+
+// find the files in order on this thread, search them on the worker threads
+// and write their output in the order that the files were found
+fn run_ordered<W: Write + Send>(
+    opt: &CommandOptions,
+    find_patterns: &FindPatterns,
+    patterns: Option<&GrepPatterns>,
+    colours: &Colours,
+    out: &mut W,
+    on_error: &OnError,
+) -> Result<SearchSummary> {
+    let (path_tx, path_rx) = mpsc::channel::<(usize, FoundFile)>();
+    let path_rx = Arc::new(Mutex::new(path_rx));
+    let (output_tx, output_rx) = mpsc::channel::<(usize, Vec<u8>)>();
+
+    thread::scope(|scope| {
+        let workers: Vec<_> = (0..opt.threads)
+            .map(|_| {
+                let path_rx = path_rx.clone();
+                let output_tx = output_tx.clone();
+                scope.spawn(move || {
+                    let mut summary = SearchSummary::default();
+                    loop {
+                        let next_path = path_rx.lock().unwrap().recv();
+                        let Ok((index, found)) = next_path else { break };
+
+                        let mut buffer = vec![];
+                        // writing to a Vec cannot fail
+                        let _ = search_file(opt, patterns, colours, &found, &mut buffer, &mut summary, on_error);
+                        if output_tx.send((index, buffer)).is_err() {
+                            break;
+                        }
+                    }
+                    summary
+                })
+            })
+            .collect();
+        // only the workers hold these now so the channels close when they finish
+        drop(path_rx);
+        drop(output_tx);
+
+        let writer = scope.spawn(move || -> Result<()> {
+            let mut pending = BTreeMap::new();
+            let mut next_index = 0;
+
+            for (index, buffer) in output_rx {
+                pending.insert(index, buffer);
+                while let Some(buffer) = pending.remove(&next_index) {
+                    out.write_all(&buffer).map_err(SfindError::Output)?;
+                    next_index += 1;
+                }
+            }
+            Ok(())
+        });
+
+        let mut summary = SearchSummary::default();
+        let found_files = FindFiles::new(opt, find_patterns).filter_map(|found| found.map_err(|e| summary.report_error(&e, on_error)).ok());
+        for (index, found) in found_files.enumerate() {
+            if path_tx.send((index, found)).is_err() {
+                break;
+            }
+        }
+        drop(path_tx);
+
+        for worker in workers {
+            summary.add(&worker.join().unwrap());
+        }
+        writer.join().unwrap()?;
+        Ok(summary)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{test_config, test_options, ScratchFolder};
+    use std::collections::HashSet;
+    use std::path::Path;
+
+    // 60 files spread over the folders that each have many lines that match
+    fn make_files(name: &str, folders: &[&str]) -> ScratchFolder {
+        let top = ScratchFolder::new(name);
+        for folder in folders {
+            for index in 0..60 / folders.len() {
+                let lines: String = (0..50).map(|line| format!("match {line}\n")).collect();
+                top.write(&format!("{folder}/file{index}.txt"), lines);
+            }
+        }
+        top
+    }
+
+    fn search(top: &Path, ordered: bool) -> String {
+        let mut args = vec!["--threads", "4", "--no-ignore", "--color=never", "-r", "match"];
+        if ordered {
+            args.push("--ordered");
+        }
+        args.push(top.to_str().unwrap());
+
+        let opt = test_options(&args);
+        let find_patterns = FindPatterns::new(&opt, &test_config()).unwrap();
+        let patterns = GrepPatterns::new(&opt).unwrap();
+
+        let mut out = vec![];
+        let summary = run(&opt, &find_patterns, Some(&patterns), &Colours::none(), &mut out, &|e| panic!("{e}")).unwrap();
+        assert_eq!(summary.files_matched, 60);
+        String::from_utf8(out).unwrap()
+    }
+
+    // the lines of each file are all together and in the order they are in the file
+    fn assert_not_interleaved(output: &str) {
+        let mut paths = HashSet::new();
+        let mut last_path = "";
+        let mut line_number = 0;
+        for line in output.lines() {
+            let mut parts = line.split(':');
+            let path = parts.next().unwrap();
+            if path != last_path {
+                assert!(paths.insert(path), "the lines of {path} are not together");
+                last_path = path;
+                line_number = 0;
+            }
+            line_number += 1;
+            assert_eq!(parts.next(), Some(line_number.to_string().as_str()));
+        }
+        assert_eq!(paths.len(), 60);
+    }
+
+    #[test]
+    fn ordered_output() {
+        let top = make_files("ordered", &["a", "b", "c"]);
+        let first = search(top.path(), true);
+        assert_not_interleaved(&first);
+        for _ in 0..3 {
+            assert_eq!(search(top.path(), true), first);
+        }
+    }
+
+    #[test]
+    fn unordered_output() {
+        let top = make_files("unordered", &["a", "b", "c"]);
+        assert_not_interleaved(&search(top.path(), false));
+    }
+
+    #[test]
+    fn one_folder() {
+        let top = make_files("one-folder", &["a"]);
+        assert_not_interleaved(&search(top.path(), false));
+        assert_not_interleaved(&search(top.path(), true));
+    }
+}
@@ -0,0 +1,59 @@
+// Fixtures shared by the tests of the other modules
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::command_options::CommandOptions;
+use crate::config_json::ConfigJson;
+
+// A folder for the files of one test, removed when it is dropped so that a
+// failing assertion does not leave it behind
+pub struct ScratchFolder {
+    path: PathBuf,
+}
+
+impl ScratchFolder {
+    // name must be different for each test as the tests run at the same time
+    pub fn new(name: &str) -> ScratchFolder {
+        let path = std::env::temp_dir().join(format!("sfind-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        ScratchFolder { path }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn join<P: AsRef<Path>>(&self, name: P) -> PathBuf {
+        self.path.join(name)
+    }
+
+    // write a file, making the folders it is in
+    pub fn write<C: AsRef<[u8]>>(&self, name: &str, contents: C) -> PathBuf {
+        let path = self.path.join(name);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).unwrap();
+        }
+        fs::write(&path, contents).unwrap();
+        path
+    }
+}
+
+impl Drop for ScratchFolder {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+// a config that prunes nothing, not the one of the user running the tests
+pub fn test_config() -> ConfigJson {
+    serde_json::from_str(r#"{"folders_to_prune": [], "files_to_prune": []}"#).unwrap()
+}
+
+// the options for the args that follow sfind on the command line
+pub fn test_options(args: &[&str]) -> CommandOptions {
+    let args: Vec<String> = std::iter::once("sfind").chain(args.iter().copied()).map(String::from).collect();
+    let mut opt = CommandOptions::new(&args).unwrap();
+    opt.apply_config(&test_config());
+    opt
+}
@@ -0,0 +1,81 @@
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::error::SfindError;
+
+type Result<T> = std::result::Result<T, SfindError>;
+
+// path with prefix and suffix added to the file name, x.rs becomes x.rs.orig
+fn with_suffix(path: &Path, prefix: &str, suffix: &str) -> PathBuf {
+    let mut file_name = prefix.to_string();
+    file_name.push_str(&path.file_name().unwrap_or_default().to_string_lossy());
+    file_name.push_str(suffix);
+    path.with_file_name(file_name)
+}
+
+// Replace the contents of path without leaving a half written file behind:
+// write a temporary file next to it, sync it to disk and rename it over path.
+// The permissions of path are kept and when backup is set the original is
+// copied to path.orig first. An existing path.orig is never replaced as it
+// holds the file from before an earlier --write.
+pub fn write_atomically(path: &Path, contents: &[u8], backup: bool) -> Result<()> {
+    let metadata = fs::metadata(path).map_err(|e| SfindError::io(path, e))?;
+
+    if backup {
+        let backup_path = with_suffix(path, "", ".orig");
+        let copy_to_backup = || -> std::io::Result<()> {
+            let mut backup_file = fs::OpenOptions::new().write(true).create_new(true).open(&backup_path)?;
+            std::io::copy(&mut fs::File::open(path)?, &mut backup_file)?;
+            backup_file.set_permissions(metadata.permissions())
+        };
+        match copy_to_backup() {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                return Err(SfindError::Replace {
+                    path: path.to_path_buf(),
+                    reason: format!("the backup {} already exists", backup_path.display()),
+                });
+            }
+            Err(e) => return Err(SfindError::io(&backup_path, e)),
+        }
+    }
+
+    let temp_path = with_suffix(path, ".", &format!(".sfind-{}", std::process::id()));
+    let write_temp = || -> std::io::Result<()> {
+        let mut temp_file = fs::File::create(&temp_path)?;
+        temp_file.write_all(contents)?;
+        temp_file.set_permissions(metadata.permissions())?;
+        temp_file.sync_all()?;
+        fs::rename(&temp_path, path)
+    };
+
+    write_temp().map_err(|e| {
+        // do not leave the temporary file behind
+        let _ = fs::remove_file(&temp_path);
+        SfindError::io(path, e)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::ScratchFolder;
+
+    #[test]
+    fn suffixed_paths() {
+        assert_eq!(with_suffix(Path::new("src/x.rs"), "", ".orig"), PathBuf::from("src/x.rs.orig"));
+        assert_eq!(with_suffix(Path::new("x.rs"), ".", ".tmp"), PathBuf::from(".x.rs.tmp"));
+    }
+
+    #[test]
+    fn backup_is_kept() {
+        let folder = ScratchFolder::new("backup");
+        let path = folder.write("x.txt", "first");
+
+        write_atomically(&path, b"second", true).unwrap();
+        assert!(write_atomically(&path, b"third", true).is_err());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "second");
+        assert_eq!(fs::read_to_string(folder.join("x.txt.orig")).unwrap(), "first");
+    }
+}